@@ -1,11 +1,25 @@
 // this modules will read and interpret config which is
-// as of now just the number of available buffers
+// as of now the number of available buffers and where the database lives
 #[cfg(feature = "read-config")]
 use serde::Deserialize;
+use std::{env, fmt, path::PathBuf, str::FromStr};
 #[cfg(feature = "read-config")]
-use std::{env, fs};
+use std::{fs, io, path::Path};
 
-#[derive(Clone, Copy, Debug, Default)]
+#[cfg(any(test, feature = "test-support"))]
+mod scratch;
+#[cfg(any(test, feature = "test-support"))]
+pub use scratch::ScratchDir;
+
+// Prefix that addresses the pinned list instead of the stack,
+// shared by the paste grammar and the interactive prompt
+pub const PIN_SYMBOL: char = '@';
+// Prefix of a named register like :todo
+pub const REGISTER_SYMBOL: char = ':';
+// Prefix of a stable handle like #3f2a9c1, a prefix of the content hash
+pub const HANDLE_SYMBOL: char = '#';
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
 #[cfg_attr(feature = "read-config", derive(Deserialize))]
 pub enum Base {
     Hexa = 6,
@@ -15,27 +29,214 @@ pub enum Base {
     HexaDecimal = 16,
 }
 
+// written as the number of buffers it allows, like 8
+impl FromStr for Base {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "6" => Ok(Base::Hexa),
+            "8" => Ok(Base::Octal),
+            "10" => Ok(Base::Decimal),
+            "16" => Ok(Base::HexaDecimal),
+            _ => Err(format!("unknown base {s}, expected 6, 8, 10 or 16")),
+        }
+    }
+}
+
+// What happens when something already in the history is copied again
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "read-config", derive(Deserialize))]
+pub enum Dedupe {
+    // store every copy
+    None,
+    // skip it only if it is already on top
+    Consecutive,
+    // move the older copy back to the top instead of storing it again
+    #[default]
+    MoveToTop,
+}
+
+// How buffers are put together when several of them are pasted at once,
+// a separator written in the paste sequence wins over it
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "read-config", derive(Deserialize))]
+pub enum Join {
+    // one buffer per line
+    #[default]
+    Newline,
+    // glued together as they are
+    None,
+    Space,
+    // any delimiter, like ", "
+    Custom(String),
+    // one per line with the whitespace around every buffer dropped
+    Trim,
+    // one per line without doubling the newline a buffer already ends with
+    Smart,
+}
+
+// the way it is written on the command line, custom:<delimiter> for Custom
+impl fmt::Display for Join {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Join::Newline => write!(f, "newline"),
+            Join::None => write!(f, "none"),
+            Join::Space => write!(f, "space"),
+            Join::Custom(delimiter) => write!(f, "custom:{delimiter}"),
+            Join::Trim => write!(f, "trim"),
+            Join::Smart => write!(f, "smart"),
+        }
+    }
+}
+
+impl FromStr for Join {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "newline" => Ok(Join::Newline),
+            "none" => Ok(Join::None),
+            "space" => Ok(Join::Space),
+            "trim" => Ok(Join::Trim),
+            "smart" => Ok(Join::Smart),
+            _ => match s.strip_prefix("custom:") {
+                Some(delimiter) => Ok(Join::Custom(delimiter.to_string())),
+                None => Err(format!(
+                    "unknown join {s}, expected newline, none, space, trim, smart or custom:<delimiter>"
+                )),
+            },
+        }
+    }
+}
+
+// Which tool talks to the clipboard, Auto goes by the session and what
+// is installed
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "read-config", derive(Deserialize))]
+pub enum ClipboardTool {
+    #[default]
+    Auto,
+    WlClipboard,
+    Xclip,
+    Xsel,
+}
+
+impl fmt::Display for ClipboardTool {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClipboardTool::Auto => write!(f, "auto"),
+            ClipboardTool::WlClipboard => write!(f, "wl-clipboard"),
+            ClipboardTool::Xclip => write!(f, "xclip"),
+            ClipboardTool::Xsel => write!(f, "xsel"),
+        }
+    }
+}
+
+// How much history is kept around, a limit left unset is not enforced
+// and pinned entries are never removed. Nothing is set by default, so no
+// history goes away unless it was asked for
+#[derive(Clone, Copy, Debug, Default)]
+#[cfg_attr(feature = "read-config", derive(Deserialize))]
+#[cfg_attr(feature = "read-config", serde(default))]
+pub struct Retention {
+    pub max_entries: Option<usize>,
+    // in seconds
+    pub max_age: Option<u64>,
+    pub max_total_bytes: Option<usize>,
+}
+
 #[cfg_attr(feature = "read-config", derive(Deserialize))]
+#[cfg_attr(feature = "read-config", serde(default))]
 pub struct Config {
     base: Base,
     // in seconds
     polling_rate: u16,
+    // overrides the XDG derived location of the database
+    database: Option<PathBuf>,
+    // keep the database under XDG_RUNTIME_DIR so it is gone after logout
+    ephemeral: bool,
+    dedupe: Dedupe,
+    retention: Retention,
+    join: Join,
+    clipboard: ClipboardTool,
 }
 
+const DB_DIR: &str = "smashboard";
+const DB_FILE: &str = "smash.db";
+#[cfg(feature = "read-config")]
+const CONFIG_FILE: &str = "smashboard.toml";
+
 impl Config {
     fn new(base: Base) -> Self {
         Self {
             base,
             polling_rate: 2,
+            database: None,
+            ephemeral: false,
+            dedupe: Dedupe::default(),
+            retention: Retention::default(),
+            join: Join::default(),
+            clipboard: ClipboardTool::default(),
         }
     }
 
-    pub fn base(self) -> Base {
+    pub fn base(&self) -> Base {
         self.base
     }
-    pub fn polling_rate(self) -> u16 {
+    pub fn polling_rate(&self) -> u16 {
         self.polling_rate
     }
+    pub fn set_polling_rate(&mut self, polling_rate: u16) {
+        self.polling_rate = polling_rate;
+    }
+    pub fn set_base(&mut self, base: Base) {
+        self.base = base;
+    }
+    pub fn dedupe(&self) -> Dedupe {
+        self.dedupe
+    }
+    pub fn retention(&self) -> Retention {
+        self.retention
+    }
+    pub fn join(&self) -> Join {
+        self.join.clone()
+    }
+    pub fn clipboard(&self) -> ClipboardTool {
+        self.clipboard
+    }
+    pub fn ephemeral(&self) -> bool {
+        self.ephemeral
+    }
+    pub fn set_ephemeral(&mut self, ephemeral: bool) {
+        self.ephemeral = ephemeral;
+    }
+
+    // The configured database or one in the users data directory,
+    // $XDG_DATA_HOME (~/.local/share) or $XDG_RUNTIME_DIR when ephemeral.
+    // Without those it goes in a directory named after the uid in /tmp,
+    // which the database only opens if it belongs to the user alone
+    pub fn database_path(&self) -> PathBuf {
+        if let Some(path) = &self.database {
+            return path.clone();
+        }
+        let var = |name| {
+            env::var_os(name)
+                .filter(|x| !x.is_empty())
+                .map(PathBuf::from)
+        };
+        let dir = if self.ephemeral {
+            var("XDG_RUNTIME_DIR")
+        } else {
+            var("XDG_DATA_HOME").or_else(|| var("HOME").map(|home| home.join(".local/share")))
+        };
+        let dir = dir.map(|x| x.join(DB_DIR)).unwrap_or_else(|| {
+            // SAFETY: geteuid has no preconditions and can not fail
+            let uid = unsafe { libc::geteuid() };
+            env::temp_dir().join(format!("{DB_DIR}-{uid}"))
+        });
+        dir.join(DB_FILE)
+    }
 }
 
 impl Default for Config {
@@ -44,22 +245,65 @@ impl Default for Config {
     }
 }
 
+#[cfg(feature = "read-config")]
+#[derive(Debug)]
+pub enum ConfigError {
+    // XDG_CONFIG_HOME is not set, so there is nowhere to look
+    NoConfigDir,
+    Unreadable(PathBuf, io::Error),
+    Invalid(PathBuf, toml::de::Error),
+}
+
+#[cfg(feature = "read-config")]
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::NoConfigDir => write!(
+                f,
+                "Could not find XDG_CONFIG_HOME variable in environment, or maybe it is overloaded"
+            ),
+            ConfigError::Unreadable(path, e) => {
+                write!(f, "Could not read the config file {}: {e}", path.display())
+            }
+            ConfigError::Invalid(path, e) => write!(f, "{} is not valid: {e}", path.display()),
+        }
+    }
+}
+
+#[cfg(feature = "read-config")]
+impl std::error::Error for ConfigError {}
+
+// $XDG_CONFIG_HOME/smashboard.toml
+#[cfg(feature = "read-config")]
+pub fn config_path() -> Result<PathBuf, ConfigError> {
+    env::var_os("XDG_CONFIG_HOME")
+        .filter(|x| !x.is_empty())
+        .map(|dir| PathBuf::from(dir).join(CONFIG_FILE))
+        .ok_or(ConfigError::NoConfigDir)
+}
+
+// Unlike read_config nothing is guessed, for the daemon that has a config
+// it can keep when the new one does not work
+#[cfg(feature = "read-config")]
+pub fn try_read_config() -> Result<Config, ConfigError> {
+    try_read_config_from(&config_path()?)
+}
+
+#[cfg(feature = "read-config")]
+pub fn try_read_config_from(path: &Path) -> Result<Config, ConfigError> {
+    let config_string =
+        fs::read_to_string(path).map_err(|e| ConfigError::Unreadable(path.to_path_buf(), e))?;
+    toml::from_str(&config_string).map_err(|e| ConfigError::Invalid(path.to_path_buf(), e))
+}
+
+// a missing config stops sb, one with mistakes falls back to the defaults
 #[cfg(feature = "read-config")]
 pub fn read_config() -> Config {
-    let config_filename = {
-        let mut config_dir = env::var("XDG_CONFIG_HOME").expect(
-            "Could not find XDG_CONFIG_HOME variable in environment, or maybe it is overloaded",
-        );
-        config_dir.push_str("/smashboard.toml");
-        config_dir
-    };
-    let config_string = fs::read_to_string(config_filename)
-        .expect("Could not find the config file \"smashboard.toml\" in config directory.");
-    let config: Config = toml::from_str(&config_string).unwrap_or(Config {
-        base: Base::Octal,
-        polling_rate: 2,
-    });
-    config
+    match try_read_config() {
+        Ok(config) => config,
+        Err(ConfigError::Invalid(..)) => Config::default(),
+        Err(e) => panic!("{e}"),
+    }
 }
 
 #[test]
@@ -73,3 +317,62 @@ fn t() {
     let decoded: Config = toml::from_str(toml_str).unwrap();
     println!("{:#?}", decoded.base);
 }
+
+#[test]
+#[cfg(feature = "read-config")]
+fn database_override() {
+    let toml_str = r#"
+    base = "Decimal"
+    database = "/srv/clips/smash.db"
+    dedupe = "Consecutive"
+    join = { Custom = ", " }
+    clipboard = "Xsel"
+
+    [retention]
+    max_age = 86400
+    "#;
+
+    let decoded: Config = toml::from_str(toml_str).unwrap();
+    assert_eq!(decoded.dedupe(), Dedupe::Consecutive);
+    assert_eq!(decoded.retention().max_age, Some(86400));
+    assert_eq!(decoded.join(), Join::Custom(", ".to_string()));
+    assert_eq!(decoded.clipboard(), ClipboardTool::Xsel);
+    assert_eq!("custom:, ".parse(), Ok(decoded.join()));
+    assert_eq!(decoded.retention().max_entries, None);
+    assert_eq!(decoded.retention().max_total_bytes, None);
+    assert_eq!(
+        decoded.database_path(),
+        PathBuf::from("/srv/clips/smash.db")
+    );
+    assert!(Config::default()
+        .database_path()
+        .ends_with("smashboard/smash.db"));
+}
+
+#[test]
+#[cfg(feature = "read-config")]
+fn config_errors() {
+    let dir = ScratchDir::new("config_errors");
+    let path = dir.join(CONFIG_FILE);
+    assert!(matches!(
+        try_read_config_from(&path),
+        Err(ConfigError::Unreadable(..))
+    ));
+    fs::write(&path, "base = \"Decimal\"\npolling_rate = \"soon\"\n").unwrap();
+    assert!(matches!(
+        try_read_config_from(&path),
+        Err(ConfigError::Invalid(..))
+    ));
+    fs::write(&path, "base = \"Decimal\"\n").unwrap();
+    assert_eq!(try_read_config_from(&path).unwrap().base(), Base::Decimal);
+}
+
+#[test]
+fn overrides() {
+    let mut config = Config::default();
+    config.set_base("16".parse().unwrap());
+    config.set_polling_rate(5);
+    assert_eq!(config.base(), Base::HexaDecimal);
+    assert_eq!(config.polling_rate(), 5);
+    assert!("7".parse::<Base>().is_err());
+}
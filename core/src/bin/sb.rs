@@ -2,56 +2,446 @@ use core::cli::{args, Action};
 
 #[cfg(feature = "read-config")]
 use config::read_config;
+use config::ClipboardTool;
+#[cfg(not(feature = "read-config"))]
 use config::Config;
-use core::db::Db;
-use core::grammar::check;
+use config::{PIN_SYMBOL, REGISTER_SYMBOL};
+use core::db::{Blob, Db, DbError, Entry};
+use core::grammar::{check, valid_register, Buffer, Sequence};
+use core::join::Joiner;
+use hooks::{detect, Clipboard};
+use ipc::{send, socket_path, IpcError, Request, Response, Status};
+use std::{
+    io::{self, IsTerminal, Read, Write},
+    path::Path,
+};
 
-use tui::inline::show_preview;
+use tui::inline::{show_preview, PreviewRow};
 #[cfg(feature = "interactive")]
 use tui::interactive::compose_ui;
 
-fn main() {
-    let config = Config::default();
+// the joined output is written out byte for byte, unless some of it is
+// binary and stdout is a terminal
+fn write_joined(joiner: &Joiner, blobs: &[Blob], sequence: &Sequence) -> io::Result<()> {
+    let mut stdout = io::stdout().lock();
+    match blobs.iter().find(|blob| !blob.is_text()) {
+        Some(blob) if stdout.is_terminal() => {
+            eprintln!(
+                "Not writing {} bytes of {} to a terminal, redirect the output instead.",
+                blob.data.len(),
+                blob.mime
+            );
+            Ok(())
+        }
+        _ => stdout.write_all(&joiner.join(blobs, &sequence.separators)),
+    }
+}
+
+// the joined result goes on the system clipboard and is pushed like any
+// other copy so it shows up in the history
+fn to_clipboard(
+    db: &Db,
+    tool: ClipboardTool,
+    joiner: &Joiner,
+    blobs: &[Blob],
+    sequence: &Sequence,
+) {
+    let Some(blob) = joiner.compose(blobs, &sequence.separators) else {
+        eprintln!("Binary buffers cannot be joined with anything else.");
+        std::process::exit(EXIT_INVALID);
+    };
+    let mut clipboard = match detect(tool) {
+        Ok(detected) => Clipboard::new(detected.backend, 0),
+        Err(e) => {
+            eprintln!("Could not find a clipboard to write to: {e}");
+            std::process::exit(EXIT_FAILURE);
+        }
+    };
+    if let Err(e) = clipboard.set_clipboard(blob.data.clone(), &blob.mime) {
+        eprintln!("Could not write to the clipboard: {e}");
+        std::process::exit(EXIT_FAILURE);
+    }
+    if let Err(e) = db.push(blob, Some(clipboard.source())) {
+        fail("Recording the result failed", e)
+    }
+}
+
+// where sb paste and compose both end up, so a sequence comes out the same
+// way from either of them
+fn paste_joined(
+    db: &Db,
+    tool: ClipboardTool,
+    joiner: &Joiner,
+    entries: Vec<Entry>,
+    sequence: &Sequence,
+    to_clipboard: bool,
+) {
+    let blobs: Vec<_> = entries.into_iter().map(|x| x.blob).collect();
+    if to_clipboard {
+        self::to_clipboard(db, tool, joiner, &blobs, sequence);
+    } else {
+        let _ = write_joined(joiner, &blobs, sequence);
+    }
+}
+
+// 1 is any failure that is not covered below
+const EXIT_FAILURE: i32 = 1;
+// the input asked for something that is not there
+const EXIT_INVALID: i32 = 2;
+const EXIT_CORRUPT: i32 = 3;
+const EXIT_NEWER_SCHEMA: i32 = 4;
+
+fn exit_code(e: &DbError) -> i32 {
+    match e {
+        DbError::IndexOutOfRange { .. } | DbError::Missing(_) | DbError::Ambiguous { .. } => {
+            EXIT_INVALID
+        }
+        DbError::Corrupt(_) => EXIT_CORRUPT,
+        DbError::NewerSchema { .. } => EXIT_NEWER_SCHEMA,
+        DbError::NotPrivate(_) | DbError::Sqlite(_) => EXIT_FAILURE,
+    }
+}
+
+fn fail(context: &str, e: DbError) -> ! {
+    eprintln!("{context}: {e}");
+    std::process::exit(exit_code(&e))
+}
+
+// the daemon is found through the socket next to the database
+fn daemon(db_path: &Path, request: Request) {
+    match send(&socket_path(db_path), request) {
+        Ok(Response::Status(status)) => {
+            let state = paused(&status).unwrap_or_else(|| "capturing".to_string());
+            println!("sbd is running as {}, {state}", status.pid);
+            println!("clipboard: {}", status.source);
+            println!("database: {}", status.database.display());
+            println!("stored since start: {}", status.captured);
+            println!("skipped while paused: {}", status.skipped);
+        }
+        Ok(Response::Ok) => {}
+        Ok(Response::Error(e)) => {
+            eprintln!("sbd could not {request}: {e}");
+            std::process::exit(EXIT_FAILURE);
+        }
+        Err(IpcError::NotRunning) => {
+            eprintln!("sbd is not running for {}", db_path.display());
+            std::process::exit(EXIT_FAILURE);
+        }
+        Err(e) => {
+            eprintln!("{e}");
+            std::process::exit(EXIT_FAILURE);
+        }
+    }
+}
+
+// what the headers say while the daemon stores nothing
+fn paused(status: &Status) -> Option<String> {
+    match (status.paused, status.resumes_in) {
+        (false, _) => None,
+        (true, None) => Some("capture paused".to_string()),
+        // rounded up so the last minute is not shown as 0
+        (true, Some(left)) => Some(format!(
+            "capture paused for {} more min",
+            left.as_secs().div_ceil(60)
+        )),
+    }
+}
+
+// a daemon that is not running has nothing to say
+fn capture_note(db_path: &Path) -> Option<String> {
+    match send(&socket_path(db_path), Request::Status) {
+        Ok(Response::Status(status)) => paused(&status),
+        _ => None,
+    }
+}
 
+fn titled(title: &str, note: &Option<String>) -> String {
+    match note {
+        Some(note) => format!("{title} ({note})"),
+        None => title.to_string(),
+    }
+}
+
+fn preview_row(label: String, entry: &Entry) -> PreviewRow {
+    PreviewRow {
+        label,
+        handle: Some(entry.handle()),
+        text: entry.blob.preview(),
+        age: entry.age(),
+        size: entry.size,
+    }
+}
+
+fn main() {
+    let (action, options) = args();
+    #[cfg(not(feature = "read-config"))]
+    let mut config = Config::default();
     #[cfg(feature = "read-config")]
-    let config = read_config();
+    let mut config = read_config();
 
+    options.apply(&mut config);
+    let db_path = options.db.unwrap_or_else(|| config.database_path());
     let base = config.base();
     let parser = check(base);
-    let pastes_db: Db = Db::new_connection(base).unwrap();
-    match args() {
-        Action::Show => {
-            let blobs = pastes_db.show();
-            if blobs.is_empty() {
-                println!("There is nothing here")
+    let pastes_db: Db = match Db::new_connection(&db_path, base) {
+        Ok(db) => db.with_dedupe(config.dedupe()),
+        Err(e) => fail(&format!("Could not open {}", db_path.display()), e),
+    };
+    match action {
+        Action::Daemon(request) => daemon(&db_path, request),
+        Action::Show { registers: true } => match pastes_db.registers() {
+            Ok(registers) if registers.is_empty() => println!("There are no registers yet"),
+            Ok(registers) => show_preview(vec![(
+                "Registers",
+                registers
+                    .iter()
+                    // registers are addressed by name, their handle would
+                    // not find them
+                    .map(|(name, entry)| PreviewRow {
+                        handle: None,
+                        ..preview_row(format!("{REGISTER_SYMBOL}{name}"), entry)
+                    })
+                    .collect(),
+            )]),
+            Err(e) => fail("Could not read the registers", e),
+        },
+        Action::Show { registers: false } => {
+            let (entries, pinned) = match (pastes_db.show(), pastes_db.pinned()) {
+                (Ok(entries), Ok(pinned)) => (entries, pinned),
+                (Err(e), _) | (_, Err(e)) => fail("Could not read the buffers", e),
+            };
+            let note = capture_note(&db_path);
+            if entries.is_empty() && pinned.is_empty() {
+                println!("{}", titled("There is nothing here", &note))
             } else {
-                show_preview(blobs.into_iter().enumerate().collect());
+                let title = titled("Buffers", &note);
+                show_preview(vec![
+                    (
+                        &title,
+                        entries
+                            .iter()
+                            .enumerate()
+                            .map(|(index, entry)| preview_row(index.to_string(), entry))
+                            .collect(),
+                    ),
+                    (
+                        "Pinned",
+                        pinned
+                            .iter()
+                            .enumerate()
+                            .map(|(index, entry)| {
+                                preview_row(format!("{PIN_SYMBOL}{index}"), entry)
+                            })
+                            .collect(),
+                    ),
+                ]);
             }
         }
         #[cfg(feature = "interactive")]
-        Action::Compose => {
-            let items = pastes_db.show();
-            let _ = compose_ui(base, items);
-        }
-        Action::Paste(bufs) => {
-            if let Some(buf) = bufs {
-                let indices = parser(&buf);
-                if indices.iter().all(|x| x.is_ok()) {
-                    let buffers = indices.into_iter().map(|x| x.unwrap()).collect();
-                    pastes_db.fetch(buffers).iter().for_each(|x| {
-                        println!("{x}");
-                    })
-                } else {
-                    eprintln!("Make sure the all the buffer indices are valid.");
+        Action::Compose { join, to_clipboard } => {
+            let (items, pinned) = match (pastes_db.show(), pastes_db.pinned()) {
+                (Ok(items), Ok(pinned)) => (items, pinned),
+                (Err(e), _) | (_, Err(e)) => fail("Could not read the buffers", e),
+            };
+            let previews = |entries: &[Entry]| entries.iter().map(|x| x.blob.preview()).collect();
+            // the prompt shows where the sequence stops making sense
+            // offsets count from the start of the prompt, leading spaces included
+            let check = |input: &str| match input.trim().is_empty() {
+                true => None,
+                false => parser(input).err().map(|e| (e.offset, e.message())),
+            };
+            let join = join.unwrap_or_else(|| config.join());
+            let note = capture_note(&db_path);
+            // entries past the window are only read once they are asked for
+            let load = |pinned, range| match pastes_db.listed(pinned, range) {
+                Ok(entries) => previews(&entries),
+                Err(_) => Vec::new(),
+            };
+            let composed = compose_ui(
+                base,
+                previews(&items),
+                previews(&pinned),
+                load,
+                check,
+                join,
+                note.as_deref(),
+            );
+            // the prompt is read like the sequence given to sb paste, an
+            // empty one means nothing was picked
+            let composition = match composed {
+                Ok(Some(composition)) if !composition.input.trim().is_empty() => composition,
+                _ => return,
+            };
+            let input = &composition.input;
+            let sequence = match parser(input) {
+                Ok(sequence) => sequence,
+                Err(e) => {
+                    eprintln!("{}", e.render(input));
+                    std::process::exit(EXIT_INVALID);
                 }
-            } else {
-                if let Some(paste) = pastes_db.peek() {
-                    println!("{}", paste);
+            };
+            match pastes_db.fetch_entries(sequence.buffers.clone()) {
+                Ok(entries) => paste_joined(
+                    &pastes_db,
+                    config.clipboard(),
+                    &Joiner::new(composition.join),
+                    entries,
+                    &sequence,
+                    to_clipboard || composition.to_clipboard,
+                ),
+                Err(e) => fail("Nothing was pasted", e),
+            }
+        }
+        Action::Paste {
+            buffers,
+            mime,
+            handle,
+            join,
+            to_clipboard,
+        } => {
+            let joiner = Joiner::new(join.unwrap_or_else(|| config.join()));
+            let sequence = match buffers {
+                Some(buf) => match parser(&buf) {
+                    Ok(sequence) => sequence,
+                    Err(e) => {
+                        eprintln!("{}", e.render(&buf));
+                        std::process::exit(EXIT_INVALID);
+                    }
+                },
+                None => Sequence {
+                    buffers: vec![Buffer::Stack(0)],
+                    separators: vec![],
+                },
+            };
+            match pastes_db.fetch_entries(sequence.buffers.clone()) {
+                Ok(entries) if handle => entries.iter().for_each(|x| println!("{}", x.handle())),
+                Ok(entries) if mime => entries.iter().for_each(|x| println!("{}", x.blob.mime)),
+                Ok(entries) => paste_joined(
+                    &pastes_db,
+                    config.clipboard(),
+                    &joiner,
+                    entries,
+                    &sequence,
+                    to_clipboard,
+                ),
+                Err(e) => fail("Nothing was pasted", e),
+            }
+        }
+        Action::Pin(buffer) => match parser(&buffer).map(|x| x.buffers).as_deref() {
+            Ok([Buffer::Stack(idx)]) => {
+                if let Err(e) = pastes_db.pin(*idx) {
+                    fail("Pinning failed", e)
                 }
             }
+            Err(e) => {
+                eprintln!("{}", e.render(&buffer));
+                std::process::exit(EXIT_INVALID);
+            }
+            _ => {
+                eprintln!("Pin takes the index of a single buffer on the stack.");
+                std::process::exit(EXIT_INVALID);
+            }
+        },
+        Action::Unpin(buffer) => match parser(&buffer).map(|x| x.buffers).as_deref() {
+            Ok([Buffer::Pinned(idx) | Buffer::Stack(idx)]) => {
+                if let Err(e) = pastes_db.unpin(*idx) {
+                    fail("Unpinning failed", e)
+                }
+            }
+            Err(e) => {
+                eprintln!("{}", e.render(&buffer));
+                std::process::exit(EXIT_INVALID);
+            }
+            _ => {
+                eprintln!("Unpin takes the index of a single pinned buffer.");
+                std::process::exit(EXIT_INVALID);
+            }
+        },
+        Action::Prune {
+            max_entries,
+            max_age,
+            max_bytes,
+        } => {
+            let mut retention = config.retention();
+            retention.max_entries = max_entries.or(retention.max_entries);
+            retention.max_age = max_age.or(retention.max_age);
+            retention.max_total_bytes = max_bytes.or(retention.max_total_bytes);
+            match pastes_db.prune(&retention) {
+                Ok(removed) => println!("Removed {removed} entries"),
+                Err(e) => fail("Pruning failed", e),
+            }
         }
-        Action::Copy(input) => {
-            let _ = pastes_db.push(input);
+        Action::Search {
+            query,
+            limit,
+            paste,
+            push,
+        } => {
+            // a rank past the limit should still be reachable
+            let limit = limit.max(paste.or(push).map_or(0, |rank| rank + 1));
+            let results = match pastes_db.search(&query, limit) {
+                Ok(results) => results,
+                Err(e) => fail("Searching failed", e),
+            };
+            match (paste.or(push), results.is_empty()) {
+                (_, true) => println!("Nothing matches {query}"),
+                (Some(rank), _) if rank >= results.len() => {
+                    eprintln!("There are only {} results for {query}.", results.len());
+                    std::process::exit(EXIT_INVALID);
+                }
+                (Some(rank), _) if paste.is_some() => {
+                    let blobs = [results[rank].blob.clone()];
+                    let joiner = Joiner::new(config.join());
+                    let _ = write_joined(&joiner, &blobs, &Sequence::default());
+                }
+                (Some(rank), _) => {
+                    if let Err(e) = pastes_db.push(results[rank].blob.clone(), None) {
+                        fail("Pushing the result failed", e)
+                    }
+                }
+                (None, _) => show_preview(vec![(
+                    "Results",
+                    results
+                        .iter()
+                        .enumerate()
+                        .map(|(rank, entry)| preview_row(rank.to_string(), entry))
+                        .collect(),
+                )]),
+            }
+        }
+        Action::Copy {
+            input,
+            mime,
+            register,
+        } => {
+            let data = match input {
+                Some(text) => text.into_bytes(),
+                None => {
+                    let mut data = Vec::new();
+                    if io::stdin().read_to_end(&mut data).is_err() {
+                        eprintln!("Could not read the input from stdin.");
+                        std::process::exit(EXIT_FAILURE);
+                    }
+                    data
+                }
+            };
+            let blob = Blob::new(data, &mime);
+            match register {
+                Some(name) if !valid_register(&name) => {
+                    eprintln!("Register names can only hold letters, digits and underscores.");
+                    std::process::exit(EXIT_INVALID);
+                }
+                Some(name) => {
+                    if let Err(e) = pastes_db.set_register(&name, blob) {
+                        fail(&format!("Writing to register {name} failed"), e)
+                    }
+                }
+                None => {
+                    if let Err(e) = pastes_db.push(blob, None) {
+                        fail("Copying failed", e)
+                    }
+                }
+            }
         }
     }
 }
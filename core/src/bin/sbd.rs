@@ -1,23 +1,143 @@
-use config::Base;
-use core::db::Db;
-use hooks::Clipboard;
+use core::cli::daemon_args;
+use core::daemon::{Daemon, Log};
+use signal_hook::{
+    consts::{SIGHUP, SIGINT, SIGTERM},
+    flag,
+};
+use std::{
+    env,
+    ffi::OsString,
+    io::{self, BufRead, BufReader, Write},
+    os::unix::process::CommandExt,
+    path::Path,
+    process::{Command, Stdio},
+    sync::Arc,
+};
 
-fn main() {
-    // let logfile = fs::File::open("/tmp/sblog");
-    // create a new process
-    let mut clipboard = Clipboard::new(hooks::Environment::Wayland, 2);
-    let pastebin = Db::new_connection(Base::default()).unwrap();
-    loop {
-        if let Ok(paste) = clipboard.poll() {
-            if let Ok(to_string) = String::from_utf8(paste) {
-                if let Err(_) = pastebin.push(to_string) {
-                    dbg!("Push to db failed");
-                }
-            } else {
-                dbg!("String parsing failed");
+const READY: &str = "ready";
+const FAILED: &str = "error ";
+
+// drops -d and --detach, also out of a bunch of short flags like -dv, clap
+// never takes a value starting with - on its own so these are all flags
+fn without_detach(args: impl Iterator<Item = OsString>) -> Vec<OsString> {
+    args.filter_map(|arg| match arg.to_str() {
+        Some("--detach") => None,
+        Some(flags) if flags.starts_with('-') && !flags.starts_with("--") => {
+            let rest: String = flags[1..].chars().filter(|x| *x != 'd').collect();
+            (!rest.is_empty()).then(|| format!("-{rest}").into())
+        }
+        _ => Some(arg),
+    })
+    .collect()
+}
+
+// starts sbd again without --detach in a process group of its own, so it
+// outlives the terminal it was started from, and waits until it is running
+fn detach(log_path: &Path) -> ! {
+    let args = without_detach(env::args_os().skip(1));
+    let child = env::current_exe().and_then(|exe| {
+        Command::new(exe)
+            .args(args)
+            .arg("--notify-ready")
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .process_group(0)
+            .spawn()
+    });
+    let mut child = match child {
+        Ok(child) => child,
+        Err(e) => {
+            eprintln!("Could not start sbd in the background: {e}");
+            std::process::exit(1)
+        }
+    };
+    // the child says it is ready or why it is not, or dies without a word
+    let mut line = String::new();
+    if let Some(stdout) = child.stdout.take() {
+        let _ = BufReader::new(stdout).read_line(&mut line);
+    }
+    match line.trim_end() {
+        READY => {
+            println!(
+                "sbd is running as {}, logging to {}",
+                child.id(),
+                log_path.display()
+            );
+            std::process::exit(0)
+        }
+        line => {
+            match line.strip_prefix(FAILED) {
+                Some(reason) => eprintln!("sbd could not start: {reason}"),
+                None => eprintln!(
+                    "sbd stopped before it was running, see {}",
+                    log_path.display()
+                ),
             }
-        } else {
-            dbg!("Polling failed");
+            let _ = child.wait();
+            std::process::exit(1)
         }
     }
 }
+
+// a parent that already went away is not an error
+fn notify(line: &str) {
+    let _ = writeln!(io::stdout(), "{line}");
+}
+
+fn main() {
+    let (options, overrides, lifecycle) = daemon_args();
+    let log_path = lifecycle.log.unwrap_or_else(Log::default_path);
+    if lifecycle.detach {
+        detach(&log_path);
+    }
+    let log = match Log::open(&log_path, true) {
+        Ok(log) => log,
+        Err(e) => {
+            let log = Log::stderr();
+            log.warn(&format!("could not open {}: {e}", log_path.display()));
+            log
+        }
+    };
+    let mut daemon = Daemon::new(options, overrides, log);
+    if lifecycle.notify_ready {
+        daemon = daemon.on_ready(|| notify(READY));
+    }
+
+    let stop = daemon.stop_flag();
+    for signal in [SIGTERM, SIGINT] {
+        // a second one while the first is still handled ends it right away
+        let registered = flag::register_conditional_shutdown(signal, 1, Arc::clone(&stop))
+            .and_then(|_| flag::register(signal, Arc::clone(&stop)));
+        if let Err(e) = registered {
+            daemon
+                .log()
+                .warn(&format!("signal {signal} is not handled: {e}"));
+        }
+    }
+    if let Err(e) = flag::register(SIGHUP, daemon.reload_flag()) {
+        daemon
+            .log()
+            .warn(&format!("SIGHUP will not reload the config: {e}"));
+    }
+
+    if let Err(e) = daemon.run() {
+        daemon.log().error(&e);
+        if lifecycle.notify_ready {
+            notify(&format!("{FAILED}{e}"));
+        }
+        std::process::exit(1);
+    }
+}
+
+#[test]
+fn detach_flags() {
+    let args = |x: &[&str]| without_detach(x.iter().map(OsString::from));
+    assert_eq!(args(&["-d", "--db", "a.db"]), ["--db", "a.db"]);
+    assert_eq!(args(&["--detach", "--log=-d.log"]), ["--log=-d.log"]);
+    assert_eq!(args(&["-dh"]), ["-h"]);
+    assert_eq!(
+        args(&["-hd", "--polling-rate", "3"]),
+        ["-h", "--polling-rate", "3"]
+    );
+}
@@ -0,0 +1,544 @@
+// Everything sbd does once its flags are parsed: holding the database,
+// watching the clipboard and writing down what happened
+use crate::{
+    cli::{DaemonOptions, Options},
+    db::{Blob, Db},
+};
+#[cfg(feature = "read-config")]
+use config::try_read_config;
+use config::Config;
+use hooks::{detect, Clipboard, ClipboardBackend, ClipboardError, Content};
+use ipc::{socket_path, Request, Response, Server, Status};
+use std::{
+    env, fmt,
+    fs::{self, File, OpenOptions, TryLockError},
+    io::{self, Read, Write},
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+    process,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{Receiver, RecvTimeoutError},
+        Arc,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+// how often stop, reload and socket requests are looked for while the
+// clipboard is quiet
+const TICK: Duration = Duration::from_millis(100);
+const STATE_DIR: &str = "smashboard";
+const LOG_FILE: &str = "sbd.log";
+
+// One line per event as key=value pairs, so it can be grepped and parsed
+pub struct Log {
+    file: Option<File>,
+    // also write to stderr, for running in the foreground
+    echo: bool,
+}
+
+impl Log {
+    // $XDG_STATE_HOME/smashboard/sbd.log or ~/.local/state when it is unset
+    pub fn default_path() -> PathBuf {
+        let var = |name| {
+            env::var_os(name)
+                .filter(|x| !x.is_empty())
+                .map(PathBuf::from)
+        };
+        var("XDG_STATE_HOME")
+            .or_else(|| var("HOME").map(|home| home.join(".local/state")))
+            .unwrap_or_else(env::temp_dir)
+            .join(STATE_DIR)
+            .join(LOG_FILE)
+    }
+
+    pub fn open(path: &Path, echo: bool) -> io::Result<Self> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self {
+            file: Some(file),
+            echo,
+        })
+    }
+
+    pub fn stderr() -> Self {
+        Self {
+            file: None,
+            echo: true,
+        }
+    }
+
+    pub fn info(&self, message: &str) {
+        self.write("info", message)
+    }
+
+    pub fn warn(&self, message: &str) {
+        self.write("warn", message)
+    }
+
+    pub fn error(&self, message: &str) {
+        self.write("error", message)
+    }
+
+    fn write(&self, level: &str, message: &str) {
+        let ts = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |x| x.as_secs());
+        let line = format!(
+            "ts={ts} pid={} level={level} msg={message:?}\n",
+            process::id()
+        );
+        // nothing sensible is left to do when the log itself fails
+        if let Some(mut file) = self.file.as_ref() {
+            let _ = file.write_all(line.as_bytes());
+        }
+        if self.echo {
+            eprint!("{line}");
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum LockError {
+    // another daemon holds the database, with its pid once it has written it
+    Held(Option<u32>),
+    Io(io::Error),
+}
+
+impl fmt::Display for LockError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LockError::Held(Some(pid)) => write!(f, "sbd is already running as {pid}"),
+            LockError::Held(None) => write!(f, "sbd is already running"),
+            LockError::Io(e) => write!(f, "the pid file could not be written: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for LockError {}
+
+// A pid file next to the database so two daemons never push into the same
+// one. It stays locked while the daemon runs, the kernel lets go of the
+// lock when the process dies however that happens.
+pub struct PidLock {
+    path: PathBuf,
+    file: File,
+}
+
+impl PidLock {
+    pub fn acquire(db_path: &Path) -> Result<Self, LockError> {
+        let path = db_path.with_extension("pid");
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(LockError::Io)?;
+        }
+        loop {
+            let mut file = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(false)
+                .open(&path)
+                .map_err(LockError::Io)?;
+            match file.try_lock() {
+                Ok(()) => {}
+                Err(TryLockError::WouldBlock) => {
+                    let mut pid = String::new();
+                    let _ = file.read_to_string(&mut pid);
+                    return Err(LockError::Held(pid.trim().parse().ok()));
+                }
+                Err(TryLockError::Error(e)) => return Err(LockError::Io(e)),
+            }
+            // the daemon before may have removed the file between the open
+            // and the lock, then this one locked a file nobody else can see
+            let locked = file.metadata().map_err(LockError::Io)?;
+            match fs::metadata(&path) {
+                Ok(current) if current.ino() == locked.ino() && current.dev() == locked.dev() => {}
+                _ => continue,
+            }
+            file.set_len(0).map_err(LockError::Io)?;
+            write!(file, "{}", process::id()).map_err(LockError::Io)?;
+            return Ok(Self { path, file });
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+// removed while it is still locked, so whoever comes next starts on a new file
+impl Drop for PidLock {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+        let _ = self.file.unlock();
+    }
+}
+
+type Changes = Receiver<Result<Content, ClipboardError>>;
+type Backend = Box<dyn ClipboardBackend + Send>;
+
+// what has to be built again when the config changes
+struct Running {
+    pastebin: Db,
+    changes: Changes,
+    // read from on a snapshot request, the watched one lives in its thread
+    clipboard: Clipboard,
+    source: &'static str,
+}
+
+// what has to move along with the database
+struct Claim {
+    lock: PidLock,
+    server: Server,
+}
+
+impl Claim {
+    fn new(db_path: &Path) -> Result<Self, String> {
+        let lock = PidLock::acquire(db_path).map_err(|e| e.to_string())?;
+        let socket = socket_path(db_path);
+        let server = Server::bind(&socket)
+            .map_err(|e| format!("could not listen on {}: {e}", socket.display()))?;
+        Ok(Self { lock, server })
+    }
+}
+
+pub struct Daemon {
+    options: Options,
+    overrides: DaemonOptions,
+    config: Config,
+    log: Log,
+    stop: Arc<AtomicBool>,
+    reload: Arc<AtomicBool>,
+    // builds the backend instead of detecting one from the session
+    backend: Option<Box<dyn Fn() -> Backend + Send>>,
+    // told once the database is claimed and the clipboard watched
+    ready: Option<Box<dyn FnOnce() + Send>>,
+    paused: bool,
+    // when a timed pause is over
+    resume_at: Option<Instant>,
+    // entries stored since the daemon started
+    captured: usize,
+    // copies that went by while paused
+    skipped: usize,
+}
+
+impl Daemon {
+    pub fn new(options: Options, overrides: DaemonOptions, log: Log) -> Self {
+        // there is no config to fall back on yet, so it starts from the defaults
+        let config = load(&options, &overrides).unwrap_or_else(|e| {
+            log.warn(&format!("using the default config: {e}"));
+            let mut config = Config::default();
+            options.apply(&mut config);
+            overrides.apply(&mut config);
+            config
+        });
+        Self {
+            options,
+            overrides,
+            config,
+            log,
+            stop: Arc::default(),
+            reload: Arc::default(),
+            backend: None,
+            ready: None,
+            paused: false,
+            resume_at: None,
+            captured: 0,
+            skipped: 0,
+        }
+    }
+
+    // for embedding the daemon, with a fake clipboard in the tests
+    pub fn with_backend(mut self, backend: impl Fn() -> Backend + Send + 'static) -> Self {
+        self.backend = Some(Box::new(backend));
+        self
+    }
+
+    pub fn on_ready(mut self, ready: impl FnOnce() + Send + 'static) -> Self {
+        self.ready = Some(Box::new(ready));
+        self
+    }
+
+    // set it to end run once the write at hand is done
+    pub fn stop_flag(&self) -> Arc<AtomicBool> {
+        Arc::clone(&self.stop)
+    }
+
+    // set it to read the config again
+    pub fn reload_flag(&self) -> Arc<AtomicBool> {
+        Arc::clone(&self.reload)
+    }
+
+    pub fn log(&self) -> &Log {
+        &self.log
+    }
+
+    fn db_path(&self, config: &Config) -> PathBuf {
+        self.options
+            .db
+            .clone()
+            .unwrap_or_else(|| config.database_path())
+    }
+
+    // the backend along with why it was picked
+    fn backend(&self, config: &Config) -> Result<(Backend, String), String> {
+        if let Some(backend) = &self.backend {
+            return Ok((backend(), "it was given to the daemon".to_string()));
+        }
+        let detected = detect(config.clipboard())
+            .map_err(|e| format!("there is no clipboard to watch: {e}"))?;
+        Ok((
+            detected.backend,
+            format!("{}, {}", detected.tool, detected.reason),
+        ))
+    }
+
+    fn start(&self, config: &Config) -> Result<Running, String> {
+        let db_path = self.db_path(config);
+        let (backend, reason) = self.backend(config)?;
+        self.log
+            .info(&format!("watching the clipboard through {reason}"));
+        let pastebin = Db::new_connection(&db_path, config.base())
+            .map_err(|e| format!("could not open {}: {e}", db_path.display()))?
+            .with_dedupe(config.dedupe());
+        let watched = Clipboard::new(backend, config.polling_rate().into());
+        let source = watched.source();
+        let clipboard = Clipboard::new(self.backend(config)?.0, 0);
+        Ok(Running {
+            pastebin,
+            changes: watched.watch(),
+            clipboard,
+            source,
+        })
+    }
+
+    // Watches the clipboard until the stop flag is set. A push that is
+    // under way is always finished first.
+    pub fn run(&mut self) -> Result<(), String> {
+        let mut claim = Claim::new(&self.db_path(&self.config))?;
+        let mut running = self.start(&self.config)?;
+        self.log.info(&format!(
+            "started, pid file {}, socket {}",
+            claim.lock.path().display(),
+            claim.server.path().display()
+        ));
+        if let Some(ready) = self.ready.take() {
+            ready();
+        }
+        while !self.stop.load(Ordering::Relaxed) {
+            if self.reload.swap(false, Ordering::Relaxed) {
+                match self.reload(&claim) {
+                    Ok((started, moved)) => {
+                        running = started;
+                        claim = moved.unwrap_or(claim);
+                        self.log.info("reloaded the config");
+                    }
+                    Err(e) => self.log.error(&format!("keeping the old config: {e}")),
+                }
+            }
+            if self.resume_at.is_some_and(|x| Instant::now() >= x) {
+                self.resume("the pause is over");
+            }
+            claim.server.serve(|request| self.answer(&running, request));
+            match running.changes.recv_timeout(TICK) {
+                Ok(Ok(_)) if self.paused => self.skipped += 1,
+                Ok(Ok(paste)) => {
+                    let _ = self.record(&running, paste);
+                }
+                Ok(Err(e)) => self.log.warn(&format!("reading the clipboard failed: {e}")),
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => {
+                    return Err("the clipboard watcher stopped".to_string())
+                }
+            }
+        }
+        self.log.info("stopped");
+        Ok(())
+    }
+
+    // A config that does not work keeps the daemon running as it was. A
+    // new database has to be claimed before the old one is let go.
+    fn reload(&mut self, claim: &Claim) -> Result<(Running, Option<Claim>), String> {
+        let config = load(&self.options, &self.overrides)?;
+        let db_path = self.db_path(&config);
+        let moved = match claim.lock.path() == db_path.with_extension("pid") {
+            true => None,
+            false => Some(Claim::new(&db_path)?),
+        };
+        let started = self.start(&config)?;
+        self.config = config;
+        Ok((started, moved))
+    }
+
+    fn answer(&mut self, running: &Running, request: Request) -> Response {
+        match request {
+            Request::Status => Response::Status(Status {
+                pid: process::id(),
+                paused: self.paused,
+                resumes_in: self
+                    .resume_at
+                    .map(|x| x.saturating_duration_since(Instant::now())),
+                captured: self.captured,
+                skipped: self.skipped,
+                source: running.source.to_string(),
+                database: self.db_path(&self.config),
+            }),
+            Request::Pause(time) => {
+                // the client can ask for any number of seconds
+                let resume_at = match time.map(|x| Instant::now().checked_add(x)) {
+                    Some(None) => {
+                        let secs = time.unwrap_or_default().as_secs();
+                        return Response::Error(format!("{secs}s is too long to pause for"));
+                    }
+                    resume_at => resume_at.flatten(),
+                };
+                self.paused = true;
+                self.resume_at = resume_at;
+                match time {
+                    Some(time) => self
+                        .log
+                        .info(&format!("paused for {}s over the socket", time.as_secs())),
+                    None => self.log.info("paused over the socket"),
+                }
+                Response::Ok
+            }
+            Request::Resume => {
+                self.resume("asked to over the socket");
+                Response::Ok
+            }
+            // taken even while paused, it was asked for explicitly
+            Request::Snapshot => match running.clipboard.get_clipboard() {
+                Ok(paste) => match self.record(running, paste) {
+                    Ok(()) => Response::Ok,
+                    Err(e) => Response::Error(e),
+                },
+                Err(e) => Response::Error(format!("reading the clipboard failed: {e}")),
+            },
+            Request::ReloadConfig => {
+                self.reload.store(true, Ordering::Relaxed);
+                Response::Ok
+            }
+        }
+    }
+
+    fn resume(&mut self, why: &str) {
+        if self.paused {
+            self.log.info(&format!("resumed, {why}"));
+        }
+        self.paused = false;
+        self.resume_at = None;
+    }
+
+    fn record(&mut self, running: &Running, paste: Content) -> Result<(), String> {
+        let blob = Blob::new(paste.bytes, &paste.mime);
+        let recorded = match running.pastebin.push(blob, Some(running.source)) {
+            Err(e) => Err(format!("push to the db failed: {e}")),
+            Ok(_) => {
+                self.captured += 1;
+                running
+                    .pastebin
+                    .prune(&self.config.retention())
+                    .map(|_| ())
+                    .map_err(|e| format!("pruning the db failed: {e}"))
+            }
+        };
+        if let Err(e) = &recorded {
+            self.log.error(e);
+        }
+        recorded
+    }
+}
+
+fn load(options: &Options, overrides: &DaemonOptions) -> Result<Config, String> {
+    #[cfg(not(feature = "read-config"))]
+    let mut config = Config::default();
+    #[cfg(feature = "read-config")]
+    let mut config = try_read_config().map_err(|e| e.to_string())?;
+    options.apply(&mut config);
+    overrides.apply(&mut config);
+    Ok(config)
+}
+
+#[test]
+fn pid_lock() {
+    let dir = config::ScratchDir::new("pid_lock");
+    let db = dir.join("smash.db");
+    let lock = PidLock::acquire(&db).unwrap();
+    assert_eq!(
+        fs::read_to_string(lock.path()).unwrap(),
+        process::id().to_string()
+    );
+
+    // a daemon that is still running keeps the database
+    let pid_file = lock.path().to_path_buf();
+    assert!(matches!(
+        PidLock::acquire(&db),
+        Err(LockError::Held(Some(pid))) if pid == process::id()
+    ));
+    drop(lock);
+    assert!(!pid_file.exists());
+
+    // a file left behind without a lock on it does not
+    fs::write(&pid_file, "1").unwrap();
+    let lock = PidLock::acquire(&db).unwrap();
+    assert_eq!(
+        fs::read_to_string(lock.path()).unwrap(),
+        process::id().to_string()
+    );
+    drop(lock);
+
+    // never two holders at once, however they take turns
+    let holders = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+    let threads: Vec<_> = (0..8)
+        .map(|_| {
+            let (db, holders) = (db.clone(), Arc::clone(&holders));
+            std::thread::spawn(move || {
+                for _ in 0..200 {
+                    if let Ok(lock) = PidLock::acquire(&db) {
+                        assert_eq!(holders.fetch_add(1, Ordering::SeqCst), 0);
+                        std::thread::sleep(Duration::from_micros(50));
+                        holders.fetch_sub(1, Ordering::SeqCst);
+                        drop(lock);
+                    }
+                }
+            })
+        })
+        .collect();
+    for thread in threads {
+        thread.join().unwrap();
+    }
+}
+
+#[test]
+#[cfg(feature = "read-config")]
+fn reload_keeps_config() {
+    use config::Base;
+    use hooks::FakeClipboard;
+
+    let dir = config::ScratchDir::new("reload_keeps_config");
+    let file = dir.join("smashboard.toml");
+    env::set_var("XDG_CONFIG_HOME", dir.path());
+    fs::write(&file, "base = \"Decimal\"\n").unwrap();
+    let options = Options {
+        db: Some(dir.join("smash.db")),
+        ephemeral: false,
+    };
+    let overrides = DaemonOptions {
+        polling_rate: None,
+        base: None,
+    };
+    let mut daemon = Daemon::new(options, overrides, Log::stderr())
+        .with_backend(|| Box::new(FakeClipboard::in_memory()));
+    assert_eq!(daemon.config.base(), Base::Decimal);
+    let claim = Claim::new(&daemon.db_path(&daemon.config)).unwrap();
+
+    // a typo does not bring back the defaults
+    fs::write(&file, "base = 12\n").unwrap();
+    assert!(daemon.reload(&claim).is_err());
+    // and a file that went away does not end the daemon
+    fs::remove_file(&file).unwrap();
+    assert!(daemon.reload(&claim).is_err());
+    assert_eq!(daemon.config.base(), Base::Decimal);
+}
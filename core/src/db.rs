@@ -1,6 +1,18 @@
-use config::Base;
-use rusqlite::{params, Connection, Result};
-use std::path::Path;
+use crate::grammar::Buffer;
+use config::{Base, Dedupe, Retention, HANDLE_SYMBOL};
+use rusqlite::{
+    params,
+    types::{FromSqlError, Value, ValueRef},
+    Connection, ErrorCode, OpenFlags, OptionalExtension, Transaction, TransactionBehavior,
+};
+use std::{
+    fmt,
+    fs::{self, DirBuilder, OpenOptions},
+    ops::Range,
+    os::unix::fs::{DirBuilderExt, MetadataExt, OpenOptionsExt},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
 // The database will be supporting a stack where their is no notion of
 // pop only PUSH can happen and fetching at any postsion is possible if that
@@ -8,126 +20,1120 @@ use std::path::Path;
 // the TOP and in this context peek will be called FETCH
 
 // But the indexing will be limited to 8 or 16 which will be represented as
-// octal or hexademical number respectively
+// octal or hexademical number respectively, anything deeper in the history
+// is reached with a bracketed index like [12] which the grammar hands over
+// as is, so the window is only enforced by the parser
+
+// Pinned entries are taken off the stack and get their own indices,
+// counted in the order they were pinned
+
+// Registers live in a table of their own and are only reachable by name
 
 // All the sql related things will be confined in this module
 
+// sbd keeps pushing while sb reads, so the database runs in WAL mode and
+// anything that looks at more than one row does it inside a transaction
+
+// how long a connection waits on a lock held by another process
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
 #[derive(Debug)]
 pub struct Db {
     conn: Connection,
-    top_id: usize,
     base: u8,
+    dedupe: Dedupe,
 }
 
-enum IndexErrors {
-    NotEnoughEntries,
-    NotPopulatedYet,
-    ExceededLimit,
+#[derive(Debug)]
+pub enum DbError {
+    // top is how many entries the buffer can reach right now,
+    // so only indices below it are valid
+    IndexOutOfRange { buffer: Buffer, top: usize },
+    // a register that was never written or a row that went away
+    // between working out its id and reading it
+    Missing(Buffer),
+    // a handle that is the prefix of more than one distinct entry
+    Ambiguous { buffer: Buffer, matches: usize },
+    // the database was written by a newer version of smash board
+    NewerSchema { found: usize, supported: usize },
+    Corrupt(String),
+    // the database or its directory is a symlink, belongs to someone else
+    // or other users can get into it
+    NotPrivate(PathBuf),
+    Sqlite(rusqlite::Error),
 }
 
-type Blob = String;
+pub type Result<T, E = DbError> = std::result::Result<T, E>;
 
-//TODO: make this path ramdom
-const DB_PATH: &str = "/tmp/smash.db";
+impl fmt::Display for DbError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DbError::IndexOutOfRange {
+                buffer: Buffer::Pinned(_),
+                top: 0,
+            } => write!(f, "nothing has been pinned yet"),
+            DbError::IndexOutOfRange { top: 0, .. } => write!(f, "the stack is empty"),
+            DbError::IndexOutOfRange { buffer, top } => write!(
+                f,
+                "buffer {buffer} is out of range, only {top} of them can be reached"
+            ),
+            DbError::Missing(buffer @ Buffer::Register(_)) => {
+                write!(f, "register {buffer} has not been written to")
+            }
+            DbError::Missing(buffer @ Buffer::Handle(_)) => {
+                write!(f, "no entry has the handle {buffer}")
+            }
+            DbError::Missing(buffer) => {
+                write!(f, "buffer {buffer} went away while it was being read")
+            }
+            DbError::Ambiguous { buffer, matches } => write!(
+                f,
+                "the handle {buffer} matches {matches} entries, add more characters to it"
+            ),
+            DbError::NewerSchema { found, supported } => write!(
+                f,
+                "database schema version {found} is newer than version {supported} supported by this build, upgrade smash board to open it"
+            ),
+            DbError::Corrupt(reason) => write!(f, "the database is corrupt: {reason}"),
+            DbError::NotPrivate(path) => write!(
+                f,
+                "{} has to belong to you and be closed to other users (mode 0700, 0600 for the file)",
+                path.display()
+            ),
+            DbError::Sqlite(e) => write!(f, "{e}"),
+        }
+    }
+}
 
-impl Db {
-    pub fn new_connection(base: Base) -> Result<Self> {
-        let path = Path::new(DB_PATH);
-        let (conn, top_id) = if !path.exists() {
-            let conn = Connection::open(path)?;
-            conn.execute(
-                "CREATE TABLE pastes (
-                    id    INTEGER PRIMARY KEY,
-                    paste BLOB
-                )",
-                [],
-            )?;
-            (conn, 0)
+impl std::error::Error for DbError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            DbError::Sqlite(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+// rows that do not decode into what was written are reported as
+// corruption rather than as a failing query
+impl From<rusqlite::Error> for DbError {
+    fn from(e: rusqlite::Error) -> Self {
+        match e {
+            rusqlite::Error::FromSqlConversionFailure(..)
+            | rusqlite::Error::InvalidColumnType(..)
+            | rusqlite::Error::IntegralValueOutOfRange(..) => DbError::Corrupt(e.to_string()),
+            rusqlite::Error::SqliteFailure(
+                rusqlite::ffi::Error {
+                    code: ErrorCode::DatabaseCorrupt | ErrorCode::NotADatabase,
+                    ..
+                },
+                _,
+            ) => DbError::Corrupt(e.to_string()),
+            e => DbError::Sqlite(e),
+        }
+    }
+}
+
+pub const TEXT_MIME: &str = "text/plain";
+
+// A single clipboard entry, the bytes are kept as is and the mime type
+// decides how they are shown and handed back out
+#[derive(Debug, Clone, PartialEq)]
+pub struct Blob {
+    pub data: Vec<u8>,
+    pub mime: String,
+}
+
+impl Blob {
+    pub fn new(data: Vec<u8>, mime: &str) -> Self {
+        Self {
+            data,
+            mime: mime.to_string(),
+        }
+    }
+
+    pub fn is_text(&self) -> bool {
+        self.mime.starts_with("text/")
+    }
+
+    // Some only if the blob is text and valid utf-8
+    pub fn as_text(&self) -> Option<&str> {
+        if self.is_text() {
+            std::str::from_utf8(&self.data).ok()
         } else {
-            let conn = Connection::open(path)?;
-            let top_id: usize = conn
-                .query_row("SELECT MAX(id) FROM pastes;", [], |row| row.get(0))
-                .unwrap_or(0);
-            (conn, top_id)
+            None
+        }
+    }
+
+    // FNV-1a over the bytes, it only has to be stable across runs and
+    // cheap, not cryptographically sound
+    pub fn hash(&self) -> String {
+        let mut hash: u64 = 0xcbf29ce484222325;
+        for byte in &self.data {
+            hash ^= *byte as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+        format!("{:016x}", hash)
+    }
+
+    // what gets shown in place of the content in previews
+    pub fn preview(&self) -> String {
+        match self.as_text() {
+            Some(text) => text.to_string(),
+            None => format!("<{}, {} bytes>", self.mime, self.data.len()),
+        }
+    }
+}
+
+impl From<&str> for Blob {
+    fn from(text: &str) -> Self {
+        Self::new(text.as_bytes().to_vec(), TEXT_MIME)
+    }
+}
+
+impl From<String> for Blob {
+    fn from(text: String) -> Self {
+        Self::new(text.into_bytes(), TEXT_MIME)
+    }
+}
+
+// A blob along with everything that was recorded when it was pushed
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub id: usize,
+    pub blob: Blob,
+    // seconds since the unix epoch, unknown for entries older than the column
+    pub created_at: Option<u64>,
+    pub size: usize,
+    pub hash: String,
+    pub source: Option<String>,
+}
+
+// number of hash characters shown as the handle of an entry
+pub const HANDLE_LEN: usize = 7;
+
+impl Entry {
+    // the handle stays the same for as long as the entry is around,
+    // unlike its index it does not change as more gets pushed
+    pub fn handle(&self) -> String {
+        format!(
+            "{HANDLE_SYMBOL}{}",
+            &self.hash[..HANDLE_LEN.min(self.hash.len())]
+        )
+    }
+
+    pub fn age(&self) -> Option<std::time::Duration> {
+        let created_at = UNIX_EPOCH + std::time::Duration::from_secs(self.created_at?);
+        SystemTime::now().duration_since(created_at).ok()
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|x| x.as_secs())
+        .unwrap_or(0)
+}
+
+// text is stored as TEXT so the database stays readable from sqlite3
+fn paste_value(blob: &Blob) -> Value {
+    match blob.as_text() {
+        Some(text) => Value::Text(text.to_string()),
+        None => Value::Blob(blob.data.clone()),
+    }
+}
+
+// Every word is quoted so user input is never read as fts5 query syntax
+fn fts_query(query: &str) -> String {
+    query
+        .split_whitespace()
+        .map(|word| format!("\"{}\"", word.replace('"', "\"\"")))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+// older versions stored the paste as TEXT so both storage classes are accepted
+fn paste_bytes(value: ValueRef<'_>) -> Result<Vec<u8>, FromSqlError> {
+    match value {
+        ValueRef::Text(x) | ValueRef::Blob(x) => Ok(x.to_vec()),
+        ValueRef::Null => Ok(Vec::new()),
+        _ => Err(FromSqlError::InvalidType),
+    }
+}
+
+// The file and its directory are only ever readable by the owner,
+// clipboard history is not something to share with other users
+fn create_private(path: &Path) -> std::io::Result<()> {
+    if let Some(dir) = path.parent() {
+        DirBuilder::new().recursive(true).mode(0o700).create(dir)?;
+    }
+    OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(path)?;
+    Ok(())
+}
+
+// one that is already there is only used if it is really ours, a directory
+// made up front by another user could otherwise hand them the history
+fn is_private(path: &Path) -> bool {
+    // SAFETY: geteuid has no preconditions and can not fail
+    let uid = unsafe { libc::geteuid() };
+    match fs::symlink_metadata(path) {
+        Ok(meta) => !meta.is_symlink() && meta.uid() == uid && meta.mode() & 0o077 == 0,
+        Err(_) => false,
+    }
+}
+
+// Each entry moves the schema up by one version, a database that has
+// applied the first n of them has PRAGMA user_version = n
+const MIGRATIONS: &[&str] = &[
+    // 1: the original stack
+    "CREATE TABLE pastes (
+        id    INTEGER PRIMARY KEY,
+        paste BLOB
+    );",
+    // 2: binary entries
+    "ALTER TABLE pastes ADD COLUMN mime TEXT NOT NULL DEFAULT 'text/plain';",
+    // 3: when and where an entry came from and if it is pinned
+    "ALTER TABLE pastes ADD COLUMN created_at INTEGER;
+     ALTER TABLE pastes ADD COLUMN source TEXT;
+     ALTER TABLE pastes ADD COLUMN pinned INTEGER NOT NULL DEFAULT 0;",
+    // 4: size and content hash, the hashes are filled in by backfill_hashes
+    "ALTER TABLE pastes ADD COLUMN size INTEGER;
+     ALTER TABLE pastes ADD COLUMN hash TEXT;
+     UPDATE pastes SET size = length(CAST(paste AS BLOB));
+     CREATE INDEX pastes_hash ON pastes (hash);",
+    // 5: named registers, laid out like pastes so entries read the same way
+    "CREATE TABLE registers (
+        name       TEXT PRIMARY KEY,
+        paste      BLOB,
+        mime       TEXT NOT NULL DEFAULT 'text/plain',
+        created_at INTEGER,
+        size       INTEGER,
+        hash       TEXT,
+        source     TEXT
+    );",
+    // 6: full text index over the text entries, kept in sync by triggers
+    "CREATE VIRTUAL TABLE pastes_fts USING fts5(paste, content = 'pastes', content_rowid = 'id');
+     INSERT INTO pastes_fts (rowid, paste)
+        SELECT id, paste FROM pastes WHERE mime LIKE 'text/%';
+     CREATE TRIGGER pastes_fts_insert AFTER INSERT ON pastes
+        WHEN new.mime LIKE 'text/%' BEGIN
+        INSERT INTO pastes_fts (rowid, paste) VALUES (new.id, new.paste);
+     END;
+     CREATE TRIGGER pastes_fts_delete AFTER DELETE ON pastes
+        WHEN old.mime LIKE 'text/%' BEGIN
+        INSERT INTO pastes_fts (pastes_fts, rowid, paste) VALUES ('delete', old.id, old.paste);
+     END;
+     CREATE TRIGGER pastes_fts_update AFTER UPDATE OF id, paste ON pastes
+        WHEN old.mime LIKE 'text/%' BEGIN
+        INSERT INTO pastes_fts (pastes_fts, rowid, paste) VALUES ('delete', old.id, old.paste);
+        INSERT INTO pastes_fts (rowid, paste) VALUES (new.id, new.paste);
+     END;",
+];
+
+pub const SCHEMA_VERSION: usize = MIGRATIONS.len();
+
+// Databases from before versioning never set user_version,
+// so work out from the columns how far along they are
+fn legacy_version(conn: &Connection) -> usize {
+    if conn.prepare("SELECT id FROM pastes LIMIT 0").is_err() {
+        0
+    } else if conn.prepare("SELECT mime FROM pastes LIMIT 0").is_err() {
+        1
+    } else {
+        2
+    }
+}
+
+// the version is read under the write lock so two processes opening a
+// fresh database at once do not both try to migrate it
+fn migrate(conn: &mut Connection) -> Result<()> {
+    let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+    let mut version: usize = tx.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+    if version == 0 {
+        version = legacy_version(&tx);
+    }
+    if version > SCHEMA_VERSION {
+        return Err(DbError::NewerSchema {
+            found: version,
+            supported: SCHEMA_VERSION,
+        });
+    }
+    for (applied, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        tx.execute_batch(migration)?;
+        tx.pragma_update(None, "user_version", applied + 1)?;
+    }
+    backfill_hashes(&tx)?;
+    tx.commit()?;
+    Ok(())
+}
+
+// hashing happens on the rust side, so rows from before migration 4
+// get their hash here instead of in the migration itself
+fn backfill_hashes(conn: &Connection) -> Result<()> {
+    let mut missing = conn.prepare("SELECT id, paste FROM pastes WHERE hash IS NULL")?;
+    let rows = missing
+        .query_map([], |row| Ok((row.get::<_, usize>(0)?, read_bytes(row, 1)?)))?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    let mut update = conn.prepare("UPDATE pastes SET hash = ?1 WHERE id = ?2")?;
+    for (id, data) in rows {
+        update.execute(params![Blob::new(data, TEXT_MIME).hash(), id])?;
+    }
+    Ok(())
+}
+
+fn read_bytes(row: &rusqlite::Row<'_>, idx: usize) -> rusqlite::Result<Vec<u8>> {
+    paste_bytes(row.get_ref(idx)?).map_err(|e| {
+        rusqlite::Error::FromSqlConversionFailure(idx, rusqlite::types::Type::Blob, Box::new(e))
+    })
+}
+
+fn read_entry(row: &rusqlite::Row<'_>) -> rusqlite::Result<Entry> {
+    let blob = Blob {
+        data: read_bytes(row, 1)?,
+        mime: row.get(2)?,
+    };
+    Ok(Entry {
+        id: row.get(0)?,
+        created_at: row.get(3)?,
+        size: row.get::<_, Option<usize>>(4)?.unwrap_or(blob.data.len()),
+        hash: row
+            .get::<_, Option<String>>(5)?
+            .unwrap_or_else(|| blob.hash()),
+        source: row.get(6)?,
+        blob,
+    })
+}
+
+impl Db {
+    pub fn new_connection(path: &Path, base: Base) -> Result<Self> {
+        // a symlink counts as there, even when it points nowhere
+        if fs::symlink_metadata(path).is_err() {
+            create_private(path).map_err(|_| rusqlite::Error::InvalidPath(path.to_path_buf()))?;
+        }
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
         };
+        if let Some(shared) = [dir, path].into_iter().find(|x| !is_private(x)) {
+            return Err(DbError::NotPrivate(shared.to_path_buf()));
+        }
+        let flags = OpenFlags::default() | OpenFlags::SQLITE_OPEN_NOFOLLOW;
+        let mut conn = Connection::open_with_flags(path, flags)?;
+        conn.busy_timeout(BUSY_TIMEOUT)?;
+        conn.pragma_update_and_check(None, "journal_mode", "WAL", |row| row.get::<_, String>(0))?;
+        migrate(&mut conn)?;
         Ok(Self {
             conn,
-            top_id,
             base: base as u8,
+            dedupe: Dedupe::default(),
         })
     }
 
+    pub fn with_dedupe(mut self, dedupe: Dedupe) -> Self {
+        self.dedupe = dedupe;
+        self
+    }
+
+    // every read inside sees the same snapshot, no matter what gets
+    // pushed in the meantime
+    fn read(&self) -> Result<Transaction<'_>> {
+        Ok(Transaction::new_unchecked(
+            &self.conn,
+            TransactionBehavior::Deferred,
+        )?)
+    }
+
+    // takes the write lock up front, so a read followed by a write can not
+    // be overtaken by another process
+    fn write(&self) -> Result<Transaction<'_>> {
+        Ok(Transaction::new_unchecked(
+            &self.conn,
+            TransactionBehavior::Immediate,
+        )?)
+    }
+
     // compute index by respecting the constraints imposed
     // i.e is octal or hexadecimal
-    fn compute_index(&self, idx: usize) -> Result<usize, IndexErrors> {
-        //TODO: This method should return error along with the top_id
-        if idx < self.base.into() && self.top_id > idx {
-            Ok(self.top_id - idx)
-        } else if idx > self.base.into() {
-            Err(IndexErrors::ExceededLimit)
-        } else if idx > self.top_id {
-            Err(IndexErrors::NotEnoughEntries)
-        } else {
-            // this also shows up incase of no DB present
-            // unreachable!(
-            //     "Found another edge case right here:\n Top: {},\t Requested: {}",
-            //     self.top_id, idx
-            // )
-            Err(IndexErrors::NotPopulatedYet)
+    // ids are not contiguous once entries get moved to the top, so the
+    // index is the rank counted down from the highest id
+    fn compute_index(&self, buffer: &Buffer) -> Result<usize> {
+        let (idx, pinned, query) = match buffer {
+            Buffer::Stack(idx) => (
+                *idx,
+                false,
+                "SELECT id FROM pastes WHERE pinned = 0 ORDER BY id DESC LIMIT 1 OFFSET ?1",
+            ),
+            Buffer::Pinned(idx) => (
+                *idx,
+                true,
+                "SELECT id FROM pastes WHERE pinned > 0 ORDER BY pinned LIMIT 1 OFFSET ?1",
+            ),
+            Buffer::Handle(prefix) => return self.resolve_handle(buffer, prefix),
+            // registers have no index, they are looked up by name
+            Buffer::Register(_) => return Err(DbError::Missing(buffer.clone())),
+        };
+        let id = self
+            .conn
+            .query_row(query, [idx], |row| row.get(0))
+            .optional()?;
+        if let Some(id) = id {
+            return Ok(id);
+        }
+        Err(DbError::IndexOutOfRange {
+            buffer: buffer.clone(),
+            top: self.top(pinned)?,
+        })
+    }
+
+    // copies of the same content share a hash, so a handle only counts as
+    // ambiguous when it matches different content and otherwise names
+    // the newest copy
+    fn resolve_handle(&self, buffer: &Buffer, prefix: &str) -> Result<usize> {
+        let (matches, id): (usize, Option<usize>) = self.conn.query_row(
+            "SELECT COUNT(DISTINCT hash), MAX(id) FROM pastes
+             WHERE substr(hash, 1, length(?1)) = ?1",
+            [prefix],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+        match (matches, id) {
+            (1, Some(id)) => Ok(id),
+            (0, _) | (_, None) => Err(DbError::Missing(buffer.clone())),
+            (matches, _) => Err(DbError::Ambiguous {
+                buffer: buffer.clone(),
+                matches,
+            }),
         }
     }
 
-    // push is expected to work on single blob at a time hence the string directly
-    pub fn push(&self, blob: Blob) -> Result<()> {
+    // how many entries of the stack or the pinned list have an index
+    fn top(&self, pinned: bool) -> Result<usize> {
+        Ok(self.conn.query_row(
+            "SELECT COUNT(*) FROM pastes WHERE (pinned > 0) = ?1",
+            [pinned],
+            |row| row.get(0),
+        )?)
+    }
+
+    // id of an entry holding exactly these bytes as the same mime type, the
+    // most recent one if there are several of them
+    fn find_duplicate(&self, blob: &Blob, hash: &str) -> Result<Option<usize>> {
         self.conn
-            .execute("INSERT INTO pastes (paste) VALUES (?1)", params![blob])?;
+            .query_row(
+                "SELECT MAX(id) FROM pastes
+             WHERE pinned = 0 AND hash = ?1 AND CAST(paste AS BLOB) = ?2 AND mime = ?3",
+                params![hash, blob.data, blob.mime],
+                |row| row.get(0),
+            )
+            .map_err(DbError::from)
+    }
+
+    // push is expected to work on single blob at a time, the source is
+    // the clipboard backend it was captured through if it came from a hook
+    pub fn push(&self, blob: Blob, source: Option<&str>) -> Result<()> {
+        let (size, hash) = (blob.data.len(), blob.hash());
+        let tx = self.write()?;
+        let duplicate = match self.dedupe {
+            Dedupe::None => None,
+            _ => self.find_duplicate(&blob, &hash)?,
+        };
+        let top: Option<usize> =
+            tx.query_row("SELECT MAX(id) FROM pastes WHERE pinned = 0", [], |row| {
+                row.get(0)
+            })?;
+        match duplicate {
+            Some(id) if Some(id) == top => {}
+            Some(id) if self.dedupe == Dedupe::MoveToTop => {
+                tx.execute(
+                    "UPDATE pastes SET id = (SELECT MAX(id) + 1 FROM pastes),
+                     created_at = ?1, source = ?2 WHERE id = ?3",
+                    params![now(), source, id],
+                )?;
+            }
+            _ => {
+                tx.execute(
+                    "INSERT INTO pastes (paste, mime, created_at, size, hash, source)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                    params![paste_value(&blob), blob.mime, now(), size, hash, source],
+                )?;
+            }
+        }
+        tx.commit()?;
         Ok(())
     }
 
-    // fetch is expected to work with batch of blob indices
-    pub fn fetch(&self, blobs: Vec<u8>) -> Vec<Blob> {
-        let mut query = self
-            .conn
-            .prepare("SELECT paste FROM pastes WHERE id = ?1")
-            .unwrap();
-        blobs
+    // Registers are not part of the stack, writing one replaces whatever
+    // it held before
+    pub fn set_register(&self, name: &str, blob: Blob) -> Result<()> {
+        self.conn.execute(
+            "INSERT OR REPLACE INTO registers (name, paste, mime, created_at, size, hash)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![
+                name,
+                paste_value(&blob),
+                blob.mime,
+                now(),
+                blob.data.len(),
+                blob.hash()
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn register(&self, name: &str) -> Result<Entry> {
+        self.conn
+            .query_row(
+                "SELECT rowid, paste, mime, created_at, size, hash, source
+                 FROM registers WHERE name = ?1",
+                [name],
+                read_entry,
+            )
+            .optional()?
+            .ok_or_else(|| DbError::Missing(Buffer::Register(name.to_string())))
+    }
+
+    // every register by name along with what it holds
+    pub fn registers(&self) -> Result<Vec<(String, Entry)>> {
+        let mut query = self.conn.prepare(
+            "SELECT rowid, paste, mime, created_at, size, hash, source, name
+             FROM registers ORDER BY name",
+        )?;
+        let rows = query.query_map([], |row| Ok((row.get(7)?, read_entry(row)?)))?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    // Entries matching every word of the query, best match first, this
+    // covers the whole history and not just what show can reach
+    pub fn search(&self, query: &str, limit: usize) -> Result<Vec<Entry>> {
+        let query = fts_query(query);
+        if query.is_empty() {
+            return Ok(Vec::new());
+        }
+        let mut search = self.conn.prepare(
+            "SELECT p.id, p.paste, p.mime, p.created_at, p.size, p.hash, p.source
+             FROM pastes_fts JOIN pastes p ON p.id = pastes_fts.rowid
+             WHERE pastes_fts MATCH ?1 ORDER BY rank LIMIT ?2",
+        )?;
+        let rows = search.query_map(params![query, limit], read_entry)?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    // Remove unpinned entries that fall outside any of the limits and
+    // return how many went, the file is compacted once a good part of it
+    // is left unused
+    pub fn prune(&self, retention: &Retention) -> Result<usize> {
+        let mut removed = 0;
+        let tx = self.write()?;
+        // rows migrated from before capture times were kept have none, they
+        // count as older than any limit
+        if let Some(max_age) = retention.max_age {
+            removed += tx.execute(
+                "DELETE FROM pastes WHERE pinned = 0 AND COALESCE(created_at, 0) < ?1",
+                [now().saturating_sub(max_age)],
+            )?;
+        }
+        if let Some(max_entries) = retention.max_entries {
+            removed += tx.execute(
+                "DELETE FROM pastes WHERE pinned = 0 AND id NOT IN (
+                    SELECT id FROM pastes WHERE pinned = 0 ORDER BY id DESC LIMIT ?1
+                )",
+                [max_entries],
+            )?;
+        }
+        if let Some(max_total_bytes) = retention.max_total_bytes {
+            removed += tx.execute(
+                "DELETE FROM pastes WHERE id IN (
+                    SELECT id FROM (
+                        SELECT id, SUM(size) OVER (ORDER BY id DESC) AS total
+                        FROM pastes WHERE pinned = 0
+                    ) WHERE total > ?1
+                )",
+                [max_total_bytes],
+            )?;
+        }
+        tx.commit()?;
+        // VACUUM can not run inside a transaction
+        if removed > 0 {
+            let free: usize = self
+                .conn
+                .query_row("PRAGMA freelist_count", [], |row| row.get(0))?;
+            let pages: usize = self
+                .conn
+                .query_row("PRAGMA page_count", [], |row| row.get(0))?;
+            if free * 4 > pages {
+                self.conn.execute_batch("VACUUM")?;
+            }
+        }
+        Ok(removed)
+    }
+
+    // Move the entry at idx from the stack to the end of the pinned list
+    pub fn pin(&self, idx: usize) -> Result<()> {
+        let tx = self.write()?;
+        let id = self.compute_index(&Buffer::Stack(idx))?;
+        tx.execute(
+            "UPDATE pastes SET pinned = (SELECT MAX(pinned) + 1 FROM pastes) WHERE id = ?1",
+            [id],
+        )?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    // Put a pinned entry back on the stack where it was before
+    pub fn unpin(&self, idx: usize) -> Result<()> {
+        let tx = self.write()?;
+        let id = self.compute_index(&Buffer::Pinned(idx))?;
+        tx.execute("UPDATE pastes SET pinned = 0 WHERE id = ?1", [id])?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    // fetch is expected to work with batch of blob indices, the first
+    // buffer that can not be read fails the whole batch and all of them
+    // are resolved against the same top of the stack
+    pub fn fetch(&self, blobs: Vec<Buffer>) -> Result<Vec<Blob>> {
+        Ok(self
+            .fetch_entries(blobs)?
+            .into_iter()
+            .map(|x| x.blob)
+            .collect())
+    }
+
+    pub fn fetch_entries(&self, blobs: Vec<Buffer>) -> Result<Vec<Entry>> {
+        let tx = self.read()?;
+        let mut query = tx.prepare(
+            "SELECT id, paste, mime, created_at, size, hash, source
+             FROM pastes WHERE id = ?1",
+        )?;
+        let entries = blobs
             .into_iter()
-            .map(|x| self.compute_index(x as usize))
-            .filter(|x| x.is_ok())
-            .map(|x| {
-                // TODO: make sure that error from this part dont get unnoticed
-                match x {
-                    // get(0) is fine because thier can be only one value associated with an index
-                    Ok(y) => query.query_row([y], |row| row.get(0)).unwrap(), // this part generates errors
-                    Err(_) => unreachable!(),
+            .map(|buffer| match &buffer {
+                Buffer::Register(name) => self.register(name),
+                _ => {
+                    let id = self.compute_index(&buffer)?;
+                    query
+                        .query_row([id], read_entry)
+                        .optional()?
+                        .ok_or(DbError::Missing(buffer))
                 }
             })
-            .collect()
+            .collect::<Result<Vec<_>>>()?;
+        drop(query);
+        tx.commit()?;
+        Ok(entries)
     }
 
-    pub fn peek(&self) -> Option<Blob> {
-        let fetched = self.fetch(Vec::from([0]));
-        assert!(fetched.len() == 1);
-        fetched.first().cloned()
+    pub fn peek(&self) -> Result<Blob> {
+        // a successful fetch has one blob for every buffer
+        Ok(self.fetch(vec![Buffer::Stack(0)])?.remove(0))
     }
 
-    pub fn show(&self) -> Vec<Blob> {
-        self.fetch(Vec::from_iter(0..self.base))
+    pub fn show(&self) -> Result<Vec<Entry>> {
+        self.listed(false, 0..self.base as usize)
+    }
+
+    pub fn pinned(&self) -> Result<Vec<Entry>> {
+        self.listed(true, 0..self.base as usize)
+    }
+
+    // the entries of the stack or of the pinned list whose indices fall in
+    // the range, which may go past the base like a bracketed index does
+    pub fn listed(&self, pinned: bool, range: Range<usize>) -> Result<Vec<Entry>> {
+        let query = match pinned {
+            false => {
+                "SELECT id, paste, mime, created_at, size, hash, source
+                 FROM pastes WHERE pinned = 0 ORDER BY id DESC LIMIT ?1 OFFSET ?2"
+            }
+            true => {
+                "SELECT id, paste, mime, created_at, size, hash, source
+                 FROM pastes WHERE pinned > 0 ORDER BY pinned LIMIT ?1 OFFSET ?2"
+            }
+        };
+        let mut query = self.conn.prepare(query)?;
+        let rows = query.query_map([range.len(), range.start], read_entry)?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
     }
 }
 
 #[test]
 fn db_connection() {
-    let db = Db::new_connection(Base::HexaDecimal).unwrap();
-
-    let _ = dbg!(db.push(Blob::from("Hello World")));
-    let _ = dbg!(db.push(Blob::from("Hello Mars")));
-    let _ = dbg!(db.push(Blob::from("Hello Mars")));
-    let _ = dbg!(db.push(Blob::from("Hello Venus")));
-    let _ = dbg!(db.push(Blob::from("Hello Jupiter")));
-    let _ = dbg!(db.push(Blob::from("Hello Neptune")));
-    let _ = dbg!(db.push(Blob::from("Hello Mercury")));
-    let _ = dbg!(db.push(Blob::from("Hello Uranas")));
-
-    dbg!(db.show());
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = config::ScratchDir::new("db_connection");
+    let path = dir.join("smash.db");
+    let db = Db::new_connection(&path, Base::HexaDecimal).unwrap();
+    assert!(matches!(
+        db.peek(),
+        Err(DbError::IndexOutOfRange { top: 0, .. })
+    ));
+    let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o600);
+    // nothing that someone else could have put there is opened
+    let link = dir.join("link.db");
+    std::os::unix::fs::symlink(&path, &link).unwrap();
+    assert!(matches!(
+        Db::new_connection(&link, Base::Octal),
+        Err(DbError::NotPrivate(_))
+    ));
+    let shared = dir.join("shared");
+    std::fs::create_dir(&shared).unwrap();
+    std::fs::set_permissions(&shared, std::fs::Permissions::from_mode(0o755)).unwrap();
+    assert!(matches!(
+        Db::new_connection(&shared.join("smash.db"), Base::Octal),
+        Err(DbError::NotPrivate(_))
+    ));
+
+    let _ = dbg!(db.push(Blob::from("Hello World"), None));
+    let _ = dbg!(db.push(Blob::from("Hello Mars"), None));
+    let _ = dbg!(db.push(Blob::from("Hello Mars"), None));
+    let _ = dbg!(db.push(Blob::from("Hello Venus"), None));
+    let _ = dbg!(db.push(Blob::from("Hello Jupiter"), None));
+    let _ = dbg!(db.push(Blob::from("Hello Neptune"), None));
+    let _ = dbg!(db.push(Blob::from("Hello Mercury"), None));
+    let _ = dbg!(db.push(Blob::from("Hello Uranas"), Some("wayland")));
+
+    let _ = dbg!(db.push(
+        Blob::new(vec![0x89, b'P', b'N', b'G', 0xff], "image/png"),
+        None
+    ));
+
+    let top = db.peek().unwrap();
+    assert_eq!(top.mime, "image/png");
+    assert_eq!(top.data, vec![0x89, b'P', b'N', b'G', 0xff]);
+    assert!(top.as_text().is_none());
+
+    let shown = db.show().unwrap();
+    assert_eq!(shown[0].size, 5);
+    assert_eq!(shown[0].hash, top.hash());
+    assert!(shown[0].created_at.is_some());
+    assert_eq!(shown[1].source.as_deref(), Some("wayland"));
+}
+
+#[test]
+fn db_migration() {
+    let dir = config::ScratchDir::new("db_migration");
+    let path = dir.join("smash.db");
+    create_private(&path).unwrap();
+    {
+        // the layout every database had before versioning
+        let conn = Connection::open(&path).unwrap();
+        conn.execute_batch(
+            "CREATE TABLE pastes (id INTEGER PRIMARY KEY, paste BLOB);
+             INSERT INTO pastes (paste) VALUES ('Hello World');",
+        )
+        .unwrap();
+    }
+    let db = Db::new_connection(&path, Base::Octal).unwrap();
+    let version: usize = db
+        .conn
+        .query_row("PRAGMA user_version", [], |row| row.get(0))
+        .unwrap();
+    assert_eq!(version, SCHEMA_VERSION);
+    assert_eq!(db.peek().unwrap(), Blob::from("Hello World"));
+    let entry = &db.show().unwrap()[0];
+    assert_eq!(entry.hash, Blob::from("Hello World").hash());
+    assert_eq!(entry.size, 11);
+    assert_eq!(entry.created_at, None);
+
+    db.conn
+        .pragma_update(None, "user_version", SCHEMA_VERSION + 1)
+        .unwrap();
+    drop(db);
+    assert!(matches!(
+        Db::new_connection(&path, Base::Octal),
+        Err(DbError::NewerSchema { .. })
+    ));
+}
+
+#[test]
+fn db_dedupe() {
+    let dir = config::ScratchDir::new("db_dedupe");
+    let path = dir.join("smash.db");
+    let stack = |dedupe| {
+        let _ = std::fs::remove_file(&path);
+        let db = Db::new_connection(&path, Base::Octal)
+            .unwrap()
+            .with_dedupe(dedupe);
+        for paste in ["A", "B", "B", "A"] {
+            db.push(Blob::from(paste), None).unwrap();
+        }
+        db.show()
+            .unwrap()
+            .into_iter()
+            .map(|x| x.blob.preview())
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(stack(Dedupe::None), ["A", "B", "B", "A"]);
+    assert_eq!(stack(Dedupe::Consecutive), ["A", "B", "A"]);
+    assert_eq!(stack(Dedupe::MoveToTop), ["A", "B"]);
+
+    // the same bytes as another type are a different entry
+    let _ = std::fs::remove_file(&path);
+    let db = Db::new_connection(&path, Base::Octal)
+        .unwrap()
+        .with_dedupe(Dedupe::MoveToTop);
+    let uri = "file:///tmp/a.png";
+    db.push(Blob::new(uri.into(), "text/uri-list"), None)
+        .unwrap();
+    db.push(Blob::from(uri), None).unwrap();
+    let mimes: Vec<_> = db
+        .show()
+        .unwrap()
+        .into_iter()
+        .map(|x| x.blob.mime)
+        .collect();
+    assert_eq!(mimes, ["text/plain", "text/uri-list"]);
+}
+
+#[test]
+fn db_prune() {
+    let dir = config::ScratchDir::new("db_prune");
+    let path = dir.join("smash.db");
+    let db = Db::new_connection(&path, Base::Octal).unwrap();
+    for paste in ["legacy", "old", "pinned", "A", "BB", "CCC"] {
+        db.push(Blob::from(paste), None).unwrap();
+    }
+    db.conn
+        .execute_batch(
+            "UPDATE pastes SET created_at = 0 WHERE paste = 'old' OR paste = 'pinned';
+             UPDATE pastes SET created_at = NULL WHERE paste = 'legacy';
+             UPDATE pastes SET pinned = 1 WHERE paste = 'pinned';",
+        )
+        .unwrap();
+    let remaining = |db: &Db| {
+        let mut query = db
+            .conn
+            .prepare("SELECT paste FROM pastes ORDER BY id")
+            .unwrap();
+        let rows = query.query_map([], |row| row.get::<_, String>(0)).unwrap();
+        rows.map(|x| x.unwrap()).collect::<Vec<_>>()
+    };
+
+    let age = Retention {
+        max_entries: None,
+        max_age: Some(60),
+        max_total_bytes: None,
+    };
+    assert_eq!(db.prune(&age).unwrap(), 2);
+    assert_eq!(remaining(&db), ["pinned", "A", "BB", "CCC"]);
+
+    let bytes = Retention {
+        max_entries: None,
+        max_age: None,
+        max_total_bytes: Some(5),
+    };
+    assert_eq!(db.prune(&bytes).unwrap(), 1);
+    assert_eq!(remaining(&db), ["pinned", "BB", "CCC"]);
+
+    let entries = Retention {
+        max_entries: Some(1),
+        max_age: None,
+        max_total_bytes: None,
+    };
+    assert_eq!(db.prune(&entries).unwrap(), 1);
+    assert_eq!(remaining(&db), ["pinned", "CCC"]);
+}
+
+#[test]
+fn db_registers() {
+    let dir = config::ScratchDir::new("db_registers");
+    let path = dir.join("smash.db");
+    let db = Db::new_connection(&path, Base::Octal).unwrap();
+    db.push(Blob::from("stack"), None).unwrap();
+    db.set_register("todo", Blob::from("first")).unwrap();
+    db.set_register("todo", Blob::from("second")).unwrap();
+    db.set_register("a", Blob::from("alpha")).unwrap();
+
+    assert_eq!(
+        db.fetch(vec![
+            Buffer::Register("todo".to_string()),
+            Buffer::Stack(0),
+            Buffer::Register("a".to_string()),
+        ])
+        .unwrap(),
+        [
+            Blob::from("second"),
+            Blob::from("stack"),
+            Blob::from("alpha")
+        ]
+    );
+    let missing = Buffer::Register("missing".to_string());
+    assert!(matches!(
+        db.fetch(vec![Buffer::Stack(0), missing.clone()]),
+        Err(DbError::Missing(buffer)) if buffer == missing
+    ));
+    let registers = db.registers().unwrap();
+    let names: Vec<_> = registers.iter().map(|(name, _)| name.as_str()).collect();
+    assert_eq!(names, ["a", "todo"]);
+    assert_eq!(registers[1].1.size, 6);
+    // registers never show up on the stack
+    assert_eq!(db.show().unwrap().len(), 1);
+}
+
+#[test]
+fn db_pins() {
+    let dir = config::ScratchDir::new("db_pins");
+    let path = dir.join("smash.db");
+    let db = Db::new_connection(&path, Base::Octal).unwrap();
+    for paste in ["A", "B", "C"] {
+        db.push(Blob::from(paste), None).unwrap();
+    }
+    let texts = |entries: Result<Vec<Entry>>| {
+        entries
+            .unwrap()
+            .into_iter()
+            .map(|x| x.blob.preview())
+            .collect::<Vec<_>>()
+    };
+
+    db.pin(1).unwrap();
+    db.pin(1).unwrap();
+    assert!(matches!(
+        db.pin(5),
+        Err(DbError::IndexOutOfRange { top: 1, .. })
+    ));
+    assert!(matches!(
+        db.fetch(vec![Buffer::Stack(9)]),
+        Err(DbError::IndexOutOfRange { top: 1, .. })
+    ));
+    assert_eq!(texts(db.show()), ["C"]);
+    assert_eq!(texts(db.pinned()), ["B", "A"]);
+    assert_eq!(
+        db.fetch(vec![Buffer::Pinned(1), Buffer::Stack(0)]).unwrap(),
+        [Blob::from("A"), Blob::from("C")]
+    );
+
+    // copying a pinned entry again still lands on the stack
+    db.push(Blob::from("B"), None).unwrap();
+    assert_eq!(texts(db.show()), ["B", "C"]);
+
+    db.unpin(0).unwrap();
+    assert_eq!(texts(db.pinned()), ["A"]);
+}
+
+#[test]
+fn db_search() {
+    let dir = config::ScratchDir::new("db_search");
+    let path = dir.join("smash.db");
+    let db = Db::new_connection(&path, Base::Octal).unwrap();
+    for paste in [
+        "cargo build --release",
+        "git push origin main",
+        "cargo test -- --nocapture",
+        "ssh \"prod\" box",
+    ] {
+        db.push(Blob::from(paste), None).unwrap();
+    }
+    db.push(Blob::new(b"cargo".to_vec(), "image/png"), None)
+        .unwrap();
+    let found = |query, limit| {
+        db.search(query, limit)
+            .unwrap()
+            .into_iter()
+            .map(|x| x.blob.preview())
+            .collect::<Vec<_>>()
+    };
+
+    assert_eq!(found("cargo", 10).len(), 2);
+    assert_eq!(found("cargo", 1).len(), 1);
+    assert_eq!(found("cargo release", 10), ["cargo build --release"]);
+    assert_eq!(found("\"prod\"", 10), ["ssh \"prod\" box"]);
+    assert!(found("--nocapture", 10).len() == 1);
+
+    // the index follows entries that move or get removed
+    db.push(Blob::from("git push origin main"), None).unwrap();
+    assert_eq!(found("origin", 10), ["git push origin main"]);
+    db.prune(&Retention {
+        max_entries: Some(1),
+        max_age: None,
+        max_total_bytes: None,
+    })
+    .unwrap();
+    assert_eq!(found("cargo", 10).len(), 0);
+    assert_eq!(found("origin", 10).len(), 1);
+}
+
+#[test]
+fn db_handles() {
+    let dir = config::ScratchDir::new("db_handles");
+    let path = dir.join("smash.db");
+    let db = Db::new_connection(&path, Base::Octal).unwrap();
+    db.push(Blob::from("A"), None).unwrap();
+    db.push(Blob::from("B"), None).unwrap();
+    let a = db.show().unwrap()[1].handle();
+    assert_eq!(a.len(), HANDLE_LEN + 1);
+    let handle = |x: &str| Buffer::Handle(x[1..].to_string());
+
+    // the index of A moves but its handle keeps pointing at it
+    db.push(Blob::from("C"), None).unwrap();
+    db.push(Blob::from("A"), None).unwrap();
+    db.push(Blob::from("D"), None).unwrap();
+    assert_eq!(db.fetch(vec![handle(&a)]).unwrap(), [Blob::from("A")]);
+
+    assert!(matches!(
+        db.fetch(vec![Buffer::Handle("".to_string())]),
+        Err(DbError::Ambiguous { matches: 4, .. })
+    ));
+    assert!(matches!(
+        db.fetch(vec![Buffer::Handle("g".to_string())]),
+        Err(DbError::Missing(_))
+    ));
+}
+
+#[test]
+fn db_deep_indices() {
+    let dir = config::ScratchDir::new("db_deep_indices");
+    let path = dir.join("smash.db");
+    let db = Db::new_connection(&path, Base::Hexa).unwrap();
+    for paste in 0..20 {
+        db.push(Blob::from(paste.to_string()), None).unwrap();
+    }
+    assert_eq!(db.show().unwrap().len(), 6);
+    assert_eq!(
+        db.fetch(vec![Buffer::Stack(12), Buffer::Stack(19)])
+            .unwrap(),
+        [Blob::from("7"), Blob::from("0")]
+    );
+    assert!(matches!(
+        db.fetch(vec![Buffer::Stack(20)]),
+        Err(DbError::IndexOutOfRange { top: 20, .. })
+    ));
+    let deeper: Vec<_> = db
+        .listed(false, 12..14)
+        .unwrap()
+        .into_iter()
+        .map(|x| x.blob)
+        .collect();
+    assert_eq!(deeper, [Blob::from("7"), Blob::from("6")]);
+    assert!(db.listed(false, 19..30).unwrap().len() == 1);
+    db.pin(15).unwrap();
+    assert_eq!(db.pinned().unwrap()[0].blob, Blob::from("4"));
+}
+
+#[test]
+fn db_capture() {
+    use hooks::{Clipboard, FakeClipboard, TEXT_MIME};
+
+    let dir = config::ScratchDir::new("db_capture");
+    let path = dir.join("smash.db");
+    let db = Db::new_connection(&path, Base::Octal).unwrap();
+    let fake = FakeClipboard::in_memory();
+    let mut watched = Clipboard::new(fake.clone(), 0);
+    let mut other = Clipboard::new(fake, 0);
+    let mut capture = |bytes: &[u8]| {
+        other.set_clipboard(bytes.to_vec(), TEXT_MIME).unwrap();
+        let paste = watched.poll().unwrap();
+        let blob = Blob::new(paste.bytes, &paste.mime);
+        db.push(blob, Some(watched.source())).unwrap();
+    };
+    capture(b"A");
+    capture(b"B");
+    capture(b"A");
+    let shown = db.show().unwrap();
+    assert_eq!(shown.len(), 2);
+    assert_eq!(shown[0].blob, Blob::from("A"));
+    assert_eq!(shown[0].source.as_deref(), Some("fake"));
 }
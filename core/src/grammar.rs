@@ -1,35 +1,620 @@
-use config::Base;
+use config::{Base, HANDLE_SYMBOL, PIN_SYMBOL, REGISTER_SYMBOL};
+use std::{iter::Peekable, str::Chars};
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum ParsingErrors {
     HigherOrderNumber,
+    MissingPinIndex,
+    MissingRegisterName,
+    MissingHandle,
+    // a bracketed index has to be a decimal number up to MAX_INDEX closed by ]
+    InvalidIndex,
+    // the end of a range is missing or of another kind than its start
+    InvalidRange,
+    // a repeat needs a count of at least one
+    InvalidRepeat,
+    // ranges and repeats together can not make more than MAX_BUFFERS
+    TooManyBuffers,
+    UnclosedSeparator,
+    // two separators between the same pair of buffers
+    DoubleSeparator,
+    // a separator with no buffer after it
+    DanglingSeparator,
+    UnexpectedCharacter,
+    EmptySequence,
 }
 
-//TODO: give some special meaning to each symbol add feature for graceful error returns
-pub fn check(cap: Base) -> impl Fn(&str) -> Vec<Result<u8, ParsingErrors>> {
-    move |line: &str| {
-        let points = line.as_bytes();
-        let mut buffers = vec![];
+impl std::fmt::Display for ParsingErrors {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let reason = match self {
+            ParsingErrors::HigherOrderNumber => "index is not valid in this base",
+            ParsingErrors::MissingPinIndex => "expected an index after the pin symbol",
+            ParsingErrors::MissingRegisterName => "expected a register name",
+            ParsingErrors::MissingHandle => "expected a handle made of hex digits",
+            ParsingErrors::InvalidIndex => "expected a decimal index closed by ]",
+            ParsingErrors::InvalidRange => "a range needs an end of the same kind as its start",
+            ParsingErrors::InvalidRepeat => "expected a repeat count of at least one",
+            ParsingErrors::TooManyBuffers => "the sequence expands to too many buffers",
+            ParsingErrors::UnclosedSeparator => "the quoted separator is never closed",
+            ParsingErrors::DoubleSeparator => "only one separator can go between two buffers",
+            ParsingErrors::DanglingSeparator => "expected a buffer after the separator",
+            ParsingErrors::UnexpectedCharacter => "unexpected character",
+            ParsingErrors::EmptySequence => "the sequence has no buffers",
+        };
+        write!(f, "{reason}")
+    }
+}
+
+// offset counts characters from the start of the sequence and found is
+// the character sitting there, None past the end of it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ParseError {
+    pub kind: ParsingErrors,
+    pub offset: usize,
+    pub found: Option<char>,
+    pub base: Base,
+}
+
+impl ParseError {
+    pub fn message(&self) -> String {
+        let base = format!("{:?}", self.base).to_lowercase();
+        match (self.kind, self.found) {
+            (ParsingErrors::HigherOrderNumber, Some(x)) => {
+                format!("index {x} is not valid in {base}")
+            }
+            (ParsingErrors::UnexpectedCharacter, Some(x)) => format!("unexpected character {x}"),
+            (kind, _) => kind.to_string(),
+        }
+    }
+
+    // what the user can do about it
+    pub fn hint(&self) -> String {
+        match self.kind {
+            ParsingErrors::HigherOrderNumber => {
+                let digit = self.found.and_then(|x| x.to_digit(36)).unwrap_or(0);
+                let fits = [Base::Hexa, Base::Octal, Base::Decimal, Base::HexaDecimal]
+                    .into_iter()
+                    .find(|base| digit < *base as u32);
+                match fits {
+                    Some(base) => {
+                        format!("switch base to {base:?} or write it as [{digit}]")
+                    }
+                    None => format!(
+                        "indices are digits of the base or decimal numbers like {OPEN_INDEX}12{CLOSE_INDEX}"
+                    ),
+                }
+            }
+            ParsingErrors::MissingPinIndex => format!("write the index right after {PIN_SYMBOL}, like {PIN_SYMBOL}0"),
+            ParsingErrors::MissingRegisterName => format!(
+                "register names are letters, digits and _, like {REGISTER_SYMBOL}todo"
+            ),
+            ParsingErrors::MissingHandle => {
+                format!("handles are the hex digits shown by sb show, like {HANDLE_SYMBOL}3f2a9c1")
+            }
+            ParsingErrors::InvalidIndex => format!("brackets hold a decimal number up to {MAX_INDEX}, like {OPEN_INDEX}12{CLOSE_INDEX}"),
+            ParsingErrors::InvalidRange => format!(
+                "both ends are on the stack or both pinned, like 0{RANGE_SYMBOL}3 or {PIN_SYMBOL}0{RANGE_SYMBOL}{PIN_SYMBOL}2"
+            ),
+            ParsingErrors::InvalidRepeat => {
+                format!("repeat with a count from 1 to 255, like 2{REPEAT_SYMBOL}3")
+            }
+            ParsingErrors::TooManyBuffers => {
+                format!("ranges and repeats can add up to {MAX_BUFFERS} buffers at most")
+            }
+            ParsingErrors::UnclosedSeparator => format!("close the separator with another {QUOTE}"),
+            ParsingErrors::DoubleSeparator => {
+                format!("keep only one of , . or a {QUOTE}quoted{QUOTE} separator")
+            }
+            ParsingErrors::DanglingSeparator => {
+                "remove the separator or add a buffer after it".to_string()
+            }
+            ParsingErrors::UnexpectedCharacter => format!(
+                "buffers look like 0, {PIN_SYMBOL}0, {OPEN_INDEX}12{CLOSE_INDEX}, {REGISTER_SYMBOL}name or {HANDLE_SYMBOL}handle and {REVERSE_SYMBOL} only goes at the start"
+            ),
+            ParsingErrors::EmptySequence => "name at least one buffer, like 0".to_string(),
+        }
+    }
+
+    // the line with a caret under the offending character, the message
+    // next to it and the hint below
+    pub fn render(&self, line: &str) -> String {
+        format!(
+            "{line}\n{:>width$} {}\nhint: {}",
+            "^",
+            self.message(),
+            self.hint(),
+            width = self.offset + 1
+        )
+    }
+}
+
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} at position {}", self.message(), self.offset)
+    }
+}
+
+// A buffer is either counted from the top of the stack or,
+// when prefixed with the pin symbol, from the list of pinned entries,
+// a single digit reaches as far as the base and [12] reaches any depth
+// or it is a register referred to by name
+// or an entry referred to by its handle, which does not move around
+#[derive(Debug, Clone, PartialEq)]
+pub enum Buffer {
+    Stack(usize),
+    Pinned(usize),
+    Register(String),
+    // lowercase hex prefix of the content hash
+    Handle(String),
+}
+
+// written the same way it is typed, 3, @1, [12] or :name
+impl std::fmt::Display for Buffer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Buffer::Stack(idx) if *idx < 10 => write!(f, "{idx}"),
+            Buffer::Stack(idx) => write!(f, "[{idx}]"),
+            Buffer::Pinned(idx) if *idx < 10 => write!(f, "{PIN_SYMBOL}{idx}"),
+            Buffer::Pinned(idx) => write!(f, "{PIN_SYMBOL}[{idx}]"),
+            Buffer::Register(name) => write!(f, "{REGISTER_SYMBOL}{name}"),
+            Buffer::Handle(prefix) => write!(f, "{HANDLE_SYMBOL}{prefix}"),
+        }
+    }
+}
+
+// What goes between two buffers when they are pasted together. Spaces
+// and . only set buffers apart like they always did, so the joiner decides
+// for them, ' ' and '' are the way to ask for a space or for nothing
+#[derive(Debug, Clone, PartialEq)]
+pub enum Separator {
+    // nothing, spaces or a . between them
+    Default,
+    // ,
+    Newline,
+    // 'text'
+    Custom(String),
+}
+
+// The buffers in the order they get pasted, separators[i] goes
+// between buffers[i] and buffers[i + 1]
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Sequence {
+    pub buffers: Vec<Buffer>,
+    pub separators: Vec<Separator>,
+}
+
+const REVERSE_SYMBOL: char = '~';
+const RANGE_SYMBOL: char = '-';
+const REPEAT_SYMBOL: char = '*';
+const QUOTE: char = '\'';
+const OPEN_INDEX: char = '[';
+const CLOSE_INDEX: char = ']';
+// deeper than any history gets, and small enough for sqlite to take
+const MAX_INDEX: usize = 999_999;
+// a hexadecimal range repeated 255 times, as much as there was before
+// bracketed indices
+const MAX_BUFFERS: usize = 4096;
+
+fn is_register_char(x: char) -> bool {
+    x.is_ascii_alphanumeric() || x == '_'
+}
+
+pub fn valid_register(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(is_register_char)
+}
+
+// keeps track of how many characters were read so errors can point at them
+struct Points<'a> {
+    chars: Peekable<Chars<'a>>,
+    offset: usize,
+    base: Base,
+}
+
+impl Points<'_> {
+    fn peek(&mut self) -> Option<char> {
+        self.chars.peek().copied()
+    }
+
+    fn next_if(&mut self, f: impl FnOnce(&char) -> bool) -> Option<char> {
+        let x = self.chars.next_if(f)?;
+        self.offset += 1;
+        Some(x)
+    }
+
+    fn next(&mut self) -> Option<char> {
+        self.next_if(|_| true)
+    }
+
+    fn error(&mut self, kind: ParsingErrors) -> ParseError {
+        ParseError {
+            kind,
+            offset: self.offset,
+            found: self.peek(),
+            base: self.base,
+        }
+    }
+
+    fn skip_spaces(&mut self) {
+        while self.next_if(|x| *x == ' ').is_some() {}
+    }
+}
+
+// this should work and yeild only a single digit number for the given base
+// or a decimal number of any size between brackets
+fn digit(cap: Base, points: &mut Points, missing: ParsingErrors) -> Result<usize, ParseError> {
+    match points.peek() {
+        Some(x) if x.is_digit(cap as u32) => {
+            points.next();
+            Ok(x.to_digit(cap as u32).unwrap_or_default() as usize)
+        }
+        Some(OPEN_INDEX) => {
+            points.next();
+            let at = points.error(ParsingErrors::InvalidIndex);
+            let mut number = String::new();
+            while let Some(x) = points.next_if(char::is_ascii_digit) {
+                number.push(x);
+            }
+            match (number.parse(), points.next_if(|x| *x == CLOSE_INDEX)) {
+                (Ok(idx), Some(_)) if idx <= MAX_INDEX => Ok(idx),
+                _ => Err(at),
+            }
+        }
+        Some(x) if x.is_ascii_alphanumeric() => Err(points.error(ParsingErrors::HigherOrderNumber)),
+        _ => Err(points.error(missing)),
+    }
+}
+
+fn buffer(cap: Base, points: &mut Points) -> Result<Buffer, ParseError> {
+    match points.peek() {
+        Some(REGISTER_SYMBOL) => {
+            points.next();
+            let mut name = String::new();
+            while let Some(x) = points.next_if(|x| is_register_char(*x)) {
+                name.push(x);
+            }
+            if name.is_empty() {
+                return Err(points.error(ParsingErrors::MissingRegisterName));
+            }
+            Ok(Buffer::Register(name))
+        }
+        Some(HANDLE_SYMBOL) => {
+            points.next();
+            let mut prefix = String::new();
+            while let Some(x) = points.next_if(char::is_ascii_hexdigit) {
+                prefix.push(x.to_ascii_lowercase());
+            }
+            if prefix.is_empty() {
+                return Err(points.error(ParsingErrors::MissingHandle));
+            }
+            Ok(Buffer::Handle(prefix))
+        }
+        Some(PIN_SYMBOL) => {
+            points.next();
+            digit(cap, points, ParsingErrors::MissingPinIndex).map(Buffer::Pinned)
+        }
+        Some(x) if x.is_ascii_alphanumeric() || x == OPEN_INDEX => {
+            digit(cap, points, ParsingErrors::HigherOrderNumber).map(Buffer::Stack)
+        }
+        None => Err(points.error(ParsingErrors::EmptySequence)),
+        Some(_) => Err(points.error(ParsingErrors::UnexpectedCharacter)),
+    }
+}
 
-        let ignore = |x: u8| {
-            let redundant = [b'.', b' ', b','];
-            let mut found = false;
-            for i in redundant {
-                found |= i == x;
+// a buffer, a range of them counting up or down, either of them repeated
+fn item(cap: Base, points: &mut Points) -> Result<Vec<Buffer>, ParseError> {
+    let start = buffer(cap, points)?;
+    let mut buffers = if points.next_if(|x| *x == RANGE_SYMBOL).is_some() {
+        let at = points.error(ParsingErrors::InvalidRange);
+        // the end of a pinned range can leave out the pin symbol
+        let pinned = points.next_if(|x| *x == PIN_SYMBOL).is_some();
+        let end = digit(cap, points, ParsingErrors::InvalidRange)?;
+        let range: Vec<usize> = match start {
+            Buffer::Stack(x) | Buffer::Pinned(x) if x.abs_diff(end) >= MAX_BUFFERS => {
+                return Err(ParseError {
+                    kind: ParsingErrors::TooManyBuffers,
+                    ..at
+                })
             }
-            found
+            Buffer::Stack(x) | Buffer::Pinned(x) if x <= end => (x..=end).collect(),
+            Buffer::Stack(x) | Buffer::Pinned(x) => (end..=x).rev().collect(),
+            _ => return Err(at),
         };
-        for token in points {
-            if ignore(*token) {
+        match start {
+            Buffer::Stack(_) if !pinned => range.into_iter().map(Buffer::Stack).collect(),
+            Buffer::Pinned(_) => range.into_iter().map(Buffer::Pinned).collect(),
+            _ => return Err(at),
+        }
+    } else {
+        vec![start]
+    };
+    if points.next_if(|x| *x == REPEAT_SYMBOL).is_some() {
+        let at = points.error(ParsingErrors::InvalidRepeat);
+        let mut count = String::new();
+        while let Some(x) = points.next_if(char::is_ascii_digit) {
+            count.push(x);
+        }
+        match count.parse::<u8>() {
+            Ok(count) if buffers.len() * count as usize > MAX_BUFFERS => {
+                return Err(ParseError {
+                    kind: ParsingErrors::TooManyBuffers,
+                    ..at
+                })
+            }
+            Ok(count) if count > 0 => {
+                let len = buffers.len() * count as usize;
+                buffers = buffers.into_iter().cycle().take(len).collect();
+            }
+            _ => return Err(at),
+        }
+    }
+    Ok(buffers)
+}
+
+// None once the end of the line is reached, spaces never mean anything
+// on their own
+fn separator(points: &mut Points) -> Result<Option<Separator>, ParseError> {
+    let mut explicit = None;
+    while let Some(x) = points.peek() {
+        let found = match x {
+            ' ' => {
+                points.next();
                 continue;
             }
-            // this should work and yeild only a single digit number for the given base
-            if let Some(number) = char::from(*token).to_digit(cap as u32) {
-                buffers.push(Ok(number as u8));
-            } else {
-                buffers.push(Err(ParsingErrors::HigherOrderNumber));
+            ',' => Separator::Newline,
+            '.' => Separator::Default,
+            QUOTE => {
+                let at = points.error(ParsingErrors::UnclosedSeparator);
+                points.next();
+                let mut text = String::new();
+                while let Some(x) = points.next_if(|x| *x != QUOTE) {
+                    text.push(x);
+                }
+                if points.next().is_none() {
+                    return Err(at);
+                }
+                if explicit.is_some() {
+                    return Err(ParseError {
+                        kind: ParsingErrors::DoubleSeparator,
+                        ..at
+                    });
+                }
+                explicit = Some(Separator::Custom(text));
+                continue;
+            }
+            _ => break,
+        };
+        if explicit.is_some() {
+            return Err(points.error(ParsingErrors::DoubleSeparator));
+        }
+        points.next();
+        explicit = Some(found);
+    }
+    match (points.peek(), explicit) {
+        (None, Some(_)) => Err(points.error(ParsingErrors::DanglingSeparator)),
+        (None, None) => Ok(None),
+        (Some(_), Some(explicit)) => Ok(Some(explicit)),
+        (Some(_), None) => Ok(Some(Separator::Default)),
+    }
+}
+
+// sequence := '~'? item (separator item)*
+// item     := buffer ('-' index)? ('*' count)?
+// a leading ~ pastes the whole sequence back to front
+pub fn check(cap: Base) -> impl Fn(&str) -> Result<Sequence, ParseError> {
+    move |line: &str| {
+        let mut points = Points {
+            chars: line.chars().peekable(),
+            offset: 0,
+            base: cap,
+        };
+        points.skip_spaces();
+        let reverse = points.next_if(|x| *x == REVERSE_SYMBOL).is_some();
+        points.skip_spaces();
+
+        let mut sequence = Sequence::default();
+        loop {
+            let at = points.error(ParsingErrors::TooManyBuffers);
+            let buffers = item(cap, &mut points)?;
+            if sequence.buffers.len() + buffers.len() > MAX_BUFFERS {
+                return Err(at);
+            }
+            for (i, buffer) in buffers.into_iter().enumerate() {
+                if i > 0 {
+                    sequence.separators.push(Separator::Default);
+                }
+                sequence.buffers.push(buffer);
+            }
+            match separator(&mut points)? {
+                Some(separator) => sequence.separators.push(separator),
+                None => break,
             }
         }
-        buffers
+        if reverse {
+            sequence.buffers.reverse();
+            sequence.separators.reverse();
+        }
+        Ok(sequence)
     }
 }
+
+#[test]
+fn pinned_buffers() {
+    let parser = check(Base::Octal);
+    let parsed = parser("0 @1,2").unwrap();
+    assert_eq!(
+        parsed.buffers,
+        [Buffer::Stack(0), Buffer::Pinned(1), Buffer::Stack(2)]
+    );
+    assert_eq!(parsed.separators, [Separator::Default, Separator::Newline]);
+    assert_eq!(
+        parser("@").unwrap_err().kind,
+        ParsingErrors::MissingPinIndex
+    );
+    assert_eq!(
+        parser("@ ").unwrap_err().kind,
+        ParsingErrors::MissingPinIndex
+    );
+    assert_eq!(
+        parser("9").unwrap_err().kind,
+        ParsingErrors::HigherOrderNumber
+    );
+}
+
+#[test]
+fn register_buffers() {
+    let parser = check(Base::HexaDecimal);
+    let parsed = parser("a:todo_1 0:b").unwrap().buffers;
+    assert_eq!(parsed.len(), 4);
+    assert_eq!(parsed[0], Buffer::Stack(10));
+    assert_eq!(parsed[1], Buffer::Register("todo_1".to_string()));
+    assert_eq!(parsed[2], Buffer::Stack(0));
+    assert_eq!(parsed[3], Buffer::Register("b".to_string()));
+    assert_eq!(
+        parser(":,1").unwrap_err().kind,
+        ParsingErrors::MissingRegisterName
+    );
+    assert!(valid_register("todo_1"));
+    assert!(!valid_register("to do"));
+}
+
+#[test]
+fn handle_buffers() {
+    let parser = check(Base::Octal);
+    let parsed = parser("#3F2a 1#0b").unwrap().buffers;
+    assert_eq!(parsed.len(), 3);
+    assert_eq!(parsed[0], Buffer::Handle("3f2a".to_string()));
+    assert_eq!(parsed[1], Buffer::Stack(1));
+    assert_eq!(parsed[2], Buffer::Handle("0b".to_string()));
+    assert_eq!(parser("#z").unwrap_err().kind, ParsingErrors::MissingHandle);
+    assert_eq!(Buffer::Handle("3f2a".to_string()).to_string(), "#3f2a");
+}
+
+#[test]
+fn ranges_and_repeats() {
+    let parser = check(Base::Octal);
+    let stack = |x: &[usize]| x.iter().copied().map(Buffer::Stack).collect::<Vec<_>>();
+    assert_eq!(parser("0-3").unwrap().buffers, stack(&[0, 1, 2, 3]));
+    assert_eq!(parser("3-1").unwrap().buffers, stack(&[3, 2, 1]));
+    assert_eq!(parser("2*3").unwrap().buffers, stack(&[2, 2, 2]));
+    assert_eq!(parser("0-1*2").unwrap().buffers, stack(&[0, 1, 0, 1]));
+    assert_eq!(
+        parser("@0-@1").unwrap().buffers,
+        [Buffer::Pinned(0), Buffer::Pinned(1)]
+    );
+    assert_eq!(
+        parser("0-@1").unwrap_err(),
+        ParseError {
+            kind: ParsingErrors::InvalidRange,
+            offset: 2,
+            found: Some('@'),
+            base: Base::Octal,
+        }
+    );
+    assert_eq!(
+        parser("1 2*0").unwrap_err(),
+        ParseError {
+            kind: ParsingErrors::InvalidRepeat,
+            offset: 4,
+            found: Some('0'),
+            base: Base::Octal,
+        }
+    );
+}
+
+#[test]
+fn separators() {
+    let parser = check(Base::Octal);
+    let parsed = parser(" ~0.1 ' | ' 2-3 ").unwrap();
+    assert_eq!(
+        parsed.buffers,
+        [
+            Buffer::Stack(3),
+            Buffer::Stack(2),
+            Buffer::Stack(1),
+            Buffer::Stack(0)
+        ]
+    );
+    assert_eq!(
+        parsed.separators,
+        [
+            Separator::Default,
+            Separator::Custom(" | ".to_string()),
+            Separator::Default
+        ]
+    );
+    // like before separators existed, 0 1 and 0.1 leave it to the joiner
+    let parsed = parser("0 1.2' '3''4").unwrap();
+    assert_eq!(
+        parsed.separators,
+        [
+            Separator::Default,
+            Separator::Default,
+            Separator::Custom(" ".to_string()),
+            Separator::Custom(String::new())
+        ]
+    );
+    let error = |line| parser(line).unwrap_err();
+    assert_eq!(error("0,").kind, ParsingErrors::DanglingSeparator);
+    assert_eq!(error("0,.1").offset, 2);
+    assert_eq!(error("0'x").kind, ParsingErrors::UnclosedSeparator);
+    assert_eq!(error("0 ~1").offset, 2);
+    assert_eq!(error("").kind, ParsingErrors::EmptySequence);
+}
+
+#[test]
+fn bracketed_indices() {
+    let parser = check(Base::Octal);
+    let parsed = parser("[12]@[3]0-[10]").unwrap().buffers;
+    assert_eq!(parsed[..2], [Buffer::Stack(12), Buffer::Pinned(3)]);
+    assert_eq!(parsed.len(), 2 + 11);
+    assert_eq!(parsed[12], Buffer::Stack(10));
+    assert_eq!(Buffer::Stack(12).to_string(), "[12]");
+    assert_eq!(
+        parser("1[12").unwrap_err(),
+        ParseError {
+            kind: ParsingErrors::InvalidIndex,
+            offset: 2,
+            found: Some('1'),
+            base: Base::Octal,
+        }
+    );
+    assert_eq!(parser("[]").unwrap_err().kind, ParsingErrors::InvalidIndex);
+    assert_eq!(parser("[a]").unwrap_err().kind, ParsingErrors::InvalidIndex);
+    assert_eq!(
+        parser("[999999]").unwrap().buffers,
+        [Buffer::Stack(999_999)]
+    );
+    let error = |line| parser(line).unwrap_err().kind;
+    assert_eq!(error("[1000000]"), ParsingErrors::InvalidIndex);
+    assert_eq!(error("[18446744073709551616]"), ParsingErrors::InvalidIndex);
+
+    // nothing gets expanded past MAX_BUFFERS
+    assert_eq!(parser("0-[4095]").unwrap().buffers.len(), 4096);
+    assert_eq!(error("0-[100000000]"), ParsingErrors::InvalidIndex);
+    assert_eq!(error("0-[999999]"), ParsingErrors::TooManyBuffers);
+    assert_eq!(error("[4096]-0"), ParsingErrors::TooManyBuffers);
+    assert_eq!(error("0-[20]*255"), ParsingErrors::TooManyBuffers);
+    assert_eq!(parser("0-[4095] 1").unwrap_err().offset, 9);
+}
+
+#[test]
+fn error_hints() {
+    let error = check(Base::Octal)("0 1 9").unwrap_err();
+    assert_eq!(error.message(), "index 9 is not valid in octal");
+    assert_eq!(error.hint(), "switch base to Decimal or write it as [9]");
+    assert_eq!(
+        error.render("0 1 9"),
+        "0 1 9\n    ^ index 9 is not valid in octal\nhint: switch base to Decimal or write it as [9]"
+    );
+    let error = check(Base::HexaDecimal)("z").unwrap_err();
+    assert_eq!(
+        error.hint(),
+        "indices are digits of the base or decimal numbers like [12]"
+    );
+    assert_eq!(
+        check(Base::Octal)("0,").unwrap_err().to_string(),
+        "expected a buffer after the separator at position 2"
+    );
+    // the caret goes under the column that was typed
+    assert_eq!(check(Base::Octal)("  9").unwrap_err().offset, 2);
+}
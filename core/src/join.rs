@@ -0,0 +1,118 @@
+use crate::{db::Blob, grammar::Separator};
+use config::Join;
+
+// Puts fetched buffers together, sb paste and compose both go through
+// this so the same selection always comes out the same way
+pub struct Joiner {
+    mode: Join,
+}
+
+impl Joiner {
+    pub fn new(mode: Join) -> Self {
+        Self { mode }
+    }
+
+    pub fn mode(&self) -> &Join {
+        &self.mode
+    }
+
+    // separators[i] goes between blobs[i] and blobs[i + 1], a missing one
+    // counts as the default, and text output ends with a newline
+    pub fn join(&self, blobs: &[Blob], separators: &[Separator]) -> Vec<u8> {
+        let mut out = self.concat(blobs, separators);
+        if blobs.last().is_some_and(Blob::is_text) {
+            self.push_newline(&mut out);
+        }
+        out
+    }
+
+    // the same without the trailing newline, which only makes sense in a
+    // terminal and not on the clipboard
+    pub fn concat(&self, blobs: &[Blob], separators: &[Separator]) -> Vec<u8> {
+        let mut out = Vec::new();
+        for (i, blob) in blobs.iter().enumerate() {
+            if i > 0 {
+                let separator = separators.get(i - 1).unwrap_or(&Separator::Default);
+                self.separate(&mut out, separator);
+            }
+            out.extend_from_slice(self.content(blob));
+        }
+        out
+    }
+
+    // what goes on the clipboard, a single binary buffer as it is and
+    // text joined together, binary data cannot be joined with anything
+    pub fn compose(&self, blobs: &[Blob], separators: &[Separator]) -> Option<Blob> {
+        match blobs {
+            [blob] if !blob.is_text() => Some(blob.clone()),
+            _ if blobs.iter().all(Blob::is_text) => {
+                let text = String::from_utf8(self.concat(blobs, separators)).ok()?;
+                Some(Blob::from(text))
+            }
+            _ => None,
+        }
+    }
+
+    fn content<'a>(&self, blob: &'a Blob) -> &'a [u8] {
+        match (&self.mode, blob.as_text()) {
+            (Join::Trim, Some(text)) => text.trim().as_bytes(),
+            _ => &blob.data,
+        }
+    }
+
+    fn separate(&self, out: &mut Vec<u8>, separator: &Separator) {
+        let delimiter = match (separator, &self.mode) {
+            (Separator::Newline, _) => "\n",
+            (Separator::Custom(text), _) => text,
+            (Separator::Default, Join::None) => "",
+            (Separator::Default, Join::Space) => " ",
+            (Separator::Default, Join::Custom(text)) => text,
+            (Separator::Default, Join::Newline | Join::Trim | Join::Smart) => "\n",
+        };
+        if delimiter == "\n" {
+            self.push_newline(out);
+        } else {
+            out.extend_from_slice(delimiter.as_bytes());
+        }
+    }
+
+    fn push_newline(&self, out: &mut Vec<u8>) {
+        if self.mode != Join::Smart || !out.ends_with(b"\n") {
+            out.push(b'\n');
+        }
+    }
+}
+
+#[test]
+fn join_modes() {
+    let blobs = [Blob::from("a\n"), Blob::from(" b "), Blob::from("c")];
+    let joined = |mode, separators: &[Separator]| {
+        String::from_utf8(Joiner::new(mode).join(&blobs, separators)).unwrap()
+    };
+    assert_eq!(joined(Join::Newline, &[]), "a\n\n b \nc\n");
+    assert_eq!(joined(Join::None, &[]), "a\n b c\n");
+    assert_eq!(joined(Join::Space, &[]), "a\n  b  c\n");
+    assert_eq!(joined(Join::Custom(", ".to_string()), &[]), "a\n,  b , c\n");
+    assert_eq!(joined(Join::Trim, &[]), "a\nb\nc\n");
+    assert_eq!(joined(Join::Smart, &[]), "a\n b \nc\n");
+    // separators from the sequence win over the mode
+    assert_eq!(
+        joined(
+            Join::Space,
+            &[Separator::Custom(String::new()), Separator::Default]
+        ),
+        "a\n b  c\n"
+    );
+
+    let binary = [Blob::new(vec![0xff], "image/png")];
+    assert_eq!(Joiner::new(Join::Newline).join(&binary, &[]), [0xff]);
+
+    let composed = Joiner::new(Join::Trim).compose(&blobs, &[]).unwrap();
+    assert_eq!(composed.as_text(), Some("a\nb\nc"));
+    assert_eq!(
+        Joiner::new(Join::Trim).compose(&binary, &[]),
+        Some(binary[0].clone())
+    );
+    let mixed = [binary[0].clone(), Blob::from("a")];
+    assert_eq!(Joiner::new(Join::Trim).compose(&mixed, &[]), None);
+}
@@ -1,43 +1,241 @@
+pub mod daemon;
 pub mod db;
 pub mod grammar;
+pub mod join;
 pub mod cli {
     use clap::{Parser, Subcommand};
-    use std::fmt::Debug;
+    use config::{Base, Config, Join};
+    use ipc::Request;
+    use std::{fmt::Debug, path::PathBuf, time::Duration};
 
     #[derive(Parser, Debug)]
     #[clap(version, about, long_about = None, arg_required_else_help = true)]
     struct Arg {
         #[clap(subcommand)]
         action: Command,
+        #[clap(flatten)]
+        options: Options,
     }
+
+    #[derive(Parser, Debug)]
+    #[clap(version, about = "Watches the clipboard and stores every entry", long_about = None)]
+    struct DaemonArg {
+        #[clap(flatten)]
+        options: Options,
+        #[clap(flatten)]
+        overrides: DaemonOptions,
+        #[clap(flatten)]
+        lifecycle: Lifecycle,
+    }
+
+    /// Flags shared between sb and sbd
+    #[derive(clap::Args, Debug)]
+    pub struct Options {
+        /// Path of the database, overrides the config and XDG locations
+        #[clap(long, global = true)]
+        pub db: Option<PathBuf>,
+        /// Keep the database under XDG_RUNTIME_DIR so it does not survive a logout
+        #[clap(long, global = true)]
+        pub ephemeral: bool,
+    }
+    /// Flags of sbd that win over the config, also after it is reloaded
+    #[derive(clap::Args, Debug)]
+    pub struct DaemonOptions {
+        /// Seconds between two looks at the clipboard when it cannot be watched
+        #[clap(long)]
+        pub polling_rate: Option<u16>,
+        /// Number of buffers that can be addressed: 6, 8, 10 or 16
+        #[clap(long)]
+        pub base: Option<Base>,
+    }
+
+    /// How sbd itself runs
+    #[derive(clap::Args, Debug)]
+    pub struct Lifecycle {
+        /// Keep running in the background, the log is all that is written then
+        #[clap(short, long)]
+        pub detach: bool,
+        /// Where the log goes instead of $XDG_STATE_HOME/smashboard/sbd.log
+        #[clap(long)]
+        pub log: Option<PathBuf>,
+        /// Write a line to stdout once running or why it could not start, for --detach
+        #[clap(long, hide = true)]
+        pub notify_ready: bool,
+    }
+
+    impl Options {
+        pub fn apply(&self, config: &mut Config) {
+            if self.ephemeral {
+                config.set_ephemeral(true);
+            }
+        }
+    }
+
+    impl DaemonOptions {
+        pub fn apply(&self, config: &mut Config) {
+            if let Some(polling_rate) = self.polling_rate {
+                config.set_polling_rate(polling_rate);
+            }
+            if let Some(base) = self.base {
+                config.set_base(base);
+            }
+        }
+    }
+
     #[derive(Subcommand, Debug)]
     enum Command {
         /// Will show upto 6, 8, 10 or 16 buffers
-        Show,
+        Show {
+            /// List the named registers instead of the stack
+            #[clap(short, long)]
+            registers: bool,
+        },
         /// Paste the content (of specified buffer)
         Paste {
+            /// Buffers like 0-2 @1; a comma puts a newline between two and 'text' puts the text,
+            /// spaces and dots leave it to --join
             #[clap(value_enum)]
             buffer_sequence: Option<String>,
+            /// Print the mime type of each buffer instead of its content
+            #[clap(short = 't', long = "type")]
+            mime: bool,
+            /// Print the stable handle of each buffer instead of its content
+            #[clap(long, conflicts_with = "mime")]
+            handle: bool,
+            /// How buffers are joined: newline, none, space, trim, smart or custom:<delimiter>
+            #[clap(short, long)]
+            join: Option<Join>,
+            /// Put the result on the system clipboard and in the history instead of printing it
+            #[clap(long, conflicts_with_all = ["mime", "handle"])]
+            to_clipboard: bool,
         },
-        /// Copy the given string to the db
+        /// Copy the given string (or stdin when absent) to the db
         Copy {
             #[clap(value_enum)]
-            input_text: String,
+            input_text: Option<String>,
+            /// Mime type the input should be stored as
+            #[clap(short, long, default_value = "text/plain")]
+            mime: String,
+            /// Write to the named register instead of the stack
+            #[clap(long)]
+            to: Option<String>,
+        },
+        /// Move a buffer off the stack into the pinned list
+        Pin {
+            #[clap(value_enum)]
+            buffer: String,
+        },
+        /// Put a pinned buffer (like @0) back on the stack
+        Unpin {
+            #[clap(value_enum)]
+            buffer: String,
+        },
+        /// Remove old entries according to the retention settings
+        Prune {
+            /// Keep at most this many unpinned entries
+            #[clap(long)]
+            max_entries: Option<usize>,
+            /// Remove entries older than this many seconds
+            #[clap(long)]
+            max_age: Option<u64>,
+            /// Keep the newest entries that fit in this many bytes
+            #[clap(long)]
+            max_bytes: Option<usize>,
+        },
+        /// Search the text entries of the whole history
+        Search {
+            query: String,
+            /// Show at most this many results
+            #[clap(short, long, default_value_t = 10)]
+            limit: usize,
+            /// Paste the result with this rank instead of listing them
+            #[clap(long, conflicts_with = "push")]
+            paste: Option<usize>,
+            /// Push the result with this rank back to the top of the stack
+            #[clap(long)]
+            push: Option<usize>,
+        },
+        /// Talk to the running sbd
+        Daemon {
+            #[clap(subcommand)]
+            command: DaemonCommand,
         },
         /// Compose together buffer interactively
         #[cfg(feature = "interactive")]
-        Compose,
+        Compose {
+            /// Join to start with, Tab cycles through the others
+            #[clap(short, long)]
+            join: Option<Join>,
+            /// Put the result on the system clipboard and in the history, Ctrl+Y does it for one run
+            #[clap(long)]
+            to_clipboard: bool,
+        },
+    }
+
+    // a timed pause is for a while, not for good
+    const MAX_PAUSE_MINUTES: u64 = 7 * 24 * 60;
+
+    #[derive(Subcommand, Debug)]
+    enum DaemonCommand {
+        /// Show whether sbd is capturing and where to
+        Status,
+        /// Stop storing what is copied until resumed, for passwords and tokens
+        #[clap(alias = "incognito")]
+        Pause {
+            /// Resume on its own after this many minutes, a week at most
+            #[clap(
+                long = "for",
+                value_name = "MINUTES",
+                value_parser = clap::value_parser!(u64).range(1..=MAX_PAUSE_MINUTES)
+            )]
+            minutes: Option<u64>,
+        },
+        /// Store what is copied again
+        Resume,
+        /// Store what the clipboard holds right now
+        Snapshot,
+        /// Read the config again
+        Reload,
     }
 
     pub enum Action {
-        Paste(Option<String>),
-        Copy(String),
-        Show,
+        Paste {
+            buffers: Option<String>,
+            mime: bool,
+            handle: bool,
+            join: Option<Join>,
+            to_clipboard: bool,
+        },
+        Copy {
+            input: Option<String>,
+            mime: String,
+            register: Option<String>,
+        },
+        Show {
+            registers: bool,
+        },
+        Pin(String),
+        Unpin(String),
+        Prune {
+            max_entries: Option<usize>,
+            max_age: Option<u64>,
+            max_bytes: Option<usize>,
+        },
+        Search {
+            query: String,
+            limit: usize,
+            paste: Option<usize>,
+            push: Option<usize>,
+        },
+        Daemon(Request),
         #[cfg(feature = "interactive")]
-        Compose,
+        Compose {
+            join: Option<Join>,
+            to_clipboard: bool,
+        },
     }
 
-    pub fn args() -> Action {
+    pub fn args() -> (Action, Options) {
         let args = match Arg::try_parse() {
             Ok(a) => a,
             Err(e) => {
@@ -45,18 +243,78 @@ pub mod cli {
                 std::process::exit(0);
             }
         };
-        match args.action {
-            Command::Paste { buffer_sequence } => {
+        let action = match args.action {
+            Command::Paste {
+                buffer_sequence,
+                mime,
+                handle,
+                join,
+                to_clipboard,
+            } => {
                 // parse the buffer sequence
-                Action::Paste(buffer_sequence)
+                Action::Paste {
+                    buffers: buffer_sequence,
+                    mime,
+                    handle,
+                    join,
+                    to_clipboard,
+                }
             }
-            Command::Copy { input_text } => {
-                //TODO: check the mime type
-                Action::Copy(input_text)
-            }
-            Command::Show => Action::Show,
+            Command::Copy {
+                input_text,
+                mime,
+                to,
+            } => Action::Copy {
+                input: input_text,
+                mime,
+                register: to,
+            },
+            Command::Show { registers } => Action::Show { registers },
+            Command::Pin { buffer } => Action::Pin(buffer),
+            Command::Unpin { buffer } => Action::Unpin(buffer),
+            Command::Prune {
+                max_entries,
+                max_age,
+                max_bytes,
+            } => Action::Prune {
+                max_entries,
+                max_age,
+                max_bytes,
+            },
+            Command::Search {
+                query,
+                limit,
+                paste,
+                push,
+            } => Action::Search {
+                query,
+                limit,
+                paste,
+                push,
+            },
+            Command::Daemon { command } => Action::Daemon(match command {
+                DaemonCommand::Status => Request::Status,
+                DaemonCommand::Pause { minutes } => {
+                    match minutes.map(|x| x.checked_mul(60).ok_or(x)).transpose() {
+                        Ok(secs) => Request::Pause(secs.map(Duration::from_secs)),
+                        Err(x) => {
+                            eprintln!("Cannot pause for {x} minutes.");
+                            std::process::exit(2);
+                        }
+                    }
+                }
+                DaemonCommand::Resume => Request::Resume,
+                DaemonCommand::Snapshot => Request::Snapshot,
+                DaemonCommand::Reload => Request::ReloadConfig,
+            }),
             #[cfg(feature = "interactive")]
-            Command::Compose => Action::Compose,
-        }
+            Command::Compose { join, to_clipboard } => Action::Compose { join, to_clipboard },
+        };
+        (action, args.options)
+    }
+
+    pub fn daemon_args() -> (Options, DaemonOptions, Lifecycle) {
+        let args = DaemonArg::parse();
+        (args.options, args.overrides, args.lifecycle)
     }
 }
@@ -0,0 +1,79 @@
+// sbd and any number of sb invocations share one database, so several
+// processes pushing and pasting at once all have to succeed and every
+// paste has to see a single snapshot of the stack
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::{Command, Output},
+    thread,
+};
+
+const WRITERS: usize = 4;
+const READERS: usize = 2;
+const ROUNDS: usize = 25;
+
+// the config sb reads lives next to the database, so whatever is on the
+// machine running the tests does not matter
+fn sb(db: &Path, args: &[&str]) -> Output {
+    let output = Command::new(env!("CARGO_BIN_EXE_sb"))
+        .env("XDG_CONFIG_HOME", db.parent().unwrap())
+        .arg("--db")
+        .arg(db)
+        .args(args)
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "sb {args:?} failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    output
+}
+
+#[test]
+fn hammer() {
+    let dir = config::ScratchDir::new("hammer");
+    let db: PathBuf = dir.join("smash.db");
+    fs::write(dir.join("smashboard.toml"), "base = \"Octal\"\n").unwrap();
+    sb(&db, &["copy", "seed-0"]);
+    sb(&db, &["copy", "seed-1"]);
+
+    let writers = (0..WRITERS).map(|writer| {
+        let db = db.clone();
+        thread::spawn(move || {
+            for round in 0..ROUNDS {
+                sb(&db, &["copy", &format!("{writer}-{round}")]);
+            }
+        })
+    });
+    let readers = (0..READERS).map(|_| {
+        let db = db.clone();
+        thread::spawn(move || {
+            for _ in 0..ROUNDS {
+                let output = sb(&db, &["paste", "01"]);
+                let pasted = String::from_utf8(output.stdout).unwrap();
+                let lines: Vec<_> = pasted.lines().collect();
+                // a push between resolving 0 and 1 would paste one entry twice
+                assert_eq!(lines.len(), 2);
+                assert_ne!(lines[0], lines[1]);
+            }
+        })
+    });
+    let handles: Vec<_> = writers.chain(readers).collect();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+
+    let conn = rusqlite::Connection::open(&db).unwrap();
+    let count: usize = conn
+        .query_row("SELECT COUNT(*) FROM pastes", [], |row| row.get(0))
+        .unwrap();
+    assert_eq!(count, WRITERS * ROUNDS + 2);
+
+    // listings go through a pipe as plain rows
+    let output = sb(&db, &["search", "seed"]);
+    let listed = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(listed.lines().count(), 2);
+    assert!(listed.lines().all(|x| x.split('\t').count() == 5));
+    assert!(sb(&db, &["show"]).stdout.starts_with(b"0\t#"));
+}
@@ -0,0 +1,137 @@
+// sbd runs in a thread of the test against a fake clipboard, and is driven
+// over its socket the same way sb daemon does it
+use core::{
+    cli::{DaemonOptions, Options},
+    daemon::{Daemon, Log},
+    db::Db,
+};
+use hooks::{ClipboardBackend, FakeClipboard, Selection};
+use ipc::{send, socket_path, Request, Response, Status};
+use std::{
+    fs,
+    path::Path,
+    process::Command,
+    sync::atomic::Ordering,
+    thread,
+    time::{Duration, Instant},
+};
+
+fn status(socket: &Path) -> Status {
+    match send(socket, Request::Status) {
+        Ok(Response::Status(status)) => status,
+        other => panic!("status answered {other:?}"),
+    }
+}
+
+// waits for the daemon to catch up with what the test did
+fn wait_for(what: &str, mut done: impl FnMut() -> bool) {
+    let start = Instant::now();
+    while !done() {
+        assert!(
+            start.elapsed() < Duration::from_secs(10),
+            "{what} timed out"
+        );
+        thread::sleep(Duration::from_millis(50));
+    }
+}
+
+fn copy(clipboard: &mut FakeClipboard, text: &str) {
+    clipboard
+        .write(Selection::Clipboard, text.as_bytes(), "text/plain")
+        .unwrap();
+}
+
+#[test]
+fn control_socket() {
+    let dir = config::ScratchDir::new("control_socket");
+    let db = dir.join("smash.db");
+    let socket = socket_path(&db);
+    let options = Options {
+        db: Some(db.clone()),
+        ephemeral: false,
+    };
+    let overrides = DaemonOptions {
+        polling_rate: Some(1),
+        base: None,
+    };
+    let mut clipboard = FakeClipboard::in_memory();
+    let fake = clipboard.clone();
+    let mut daemon =
+        Daemon::new(options, overrides, Log::stderr()).with_backend(move || Box::new(fake.clone()));
+    let stop = daemon.stop_flag();
+    let sbd = thread::spawn(move || daemon.run());
+
+    wait_for("the socket", || socket.exists());
+    let started = status(&socket);
+    assert_eq!(started.pid, std::process::id());
+    assert!(!started.paused);
+    assert_eq!(started.captured, 0);
+    assert_eq!(started.source, "fake");
+    assert_eq!(started.database, db);
+
+    copy(&mut clipboard, "first");
+    wait_for("the first copy", || status(&socket).captured == 1);
+
+    // nothing is stored while paused, sb reads a config of its own rather
+    // than whatever is on the machine
+    fs::write(dir.join("smashboard.toml"), "base = \"Octal\"\n").unwrap();
+    let sb = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_sb"))
+            .env("XDG_CONFIG_HOME", dir.path())
+            .arg("--db")
+            .arg(&db)
+            .args(args)
+            .output()
+            .unwrap()
+    };
+    assert!(sb(&["daemon", "pause"]).status.success());
+    assert!(status(&socket).paused);
+    let output = sb(&["daemon", "status"]);
+    assert!(String::from_utf8_lossy(&output.stdout).contains("paused"));
+    copy(&mut clipboard, "secret");
+    wait_for("the skipped copy", || status(&socket).skipped == 1);
+    assert_eq!(status(&socket).captured, 1);
+
+    // a snapshot is taken anyway
+    assert_eq!(send(&socket, Request::Snapshot).unwrap(), Response::Ok);
+    assert_eq!(status(&socket).captured, 2);
+    assert_eq!(send(&socket, Request::Resume).unwrap(), Response::Ok);
+    copy(&mut clipboard, "second");
+    wait_for("the second copy", || status(&socket).captured == 3);
+
+    let pastebin = Db::new_connection(&db, config::Base::Decimal).unwrap();
+    let stack: Vec<_> = pastebin
+        .show()
+        .unwrap()
+        .into_iter()
+        .map(|x| x.blob.data)
+        .collect();
+    assert_eq!(stack, [&b"second"[..], b"secret", b"first"]);
+
+    // incognito for a while, it ends on its own
+    assert!(sb(&["daemon", "incognito", "--for", "5"]).status.success());
+    let paused = status(&socket);
+    assert!(paused.paused);
+    assert!(paused.resumes_in.unwrap() > Duration::from_secs(290));
+    let output = sb(&["daemon", "status"]);
+    let expected = "capture paused for 5 more min";
+    assert!(String::from_utf8_lossy(&output.stdout).contains(expected));
+    // more than an Instant can hold is turned down, the daemon keeps going
+    let endless = Request::Pause(Some(Duration::from_secs(u64::MAX)));
+    assert!(matches!(send(&socket, endless), Ok(Response::Error(_))));
+    let output = sb(&["daemon", "pause", "--for", "200000000000000000"]);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("200000000000000000"));
+    assert!(status(&socket).resumes_in.unwrap() > Duration::from_secs(280));
+    let timed = Request::Pause(Some(Duration::from_secs(1)));
+    assert_eq!(send(&socket, timed).unwrap(), Response::Ok);
+    wait_for("the pause to end", || !status(&socket).paused);
+    assert_eq!(status(&socket).resumes_in, None);
+    let output = sb(&["daemon", "status"]);
+    assert!(String::from_utf8_lossy(&output.stdout).contains("capturing"));
+
+    assert_eq!(send(&socket, Request::ReloadConfig).unwrap(), Response::Ok);
+    stop.store(true, Ordering::Relaxed);
+    sbd.join().unwrap().unwrap();
+    assert!(!socket.exists());
+    assert!(!sb(&["daemon", "status"]).status.success());
+}
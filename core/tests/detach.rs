@@ -0,0 +1,63 @@
+// sbd -d only reports success once the daemon it started holds the
+// database, a fake xsel on the PATH stands in for the clipboard
+use ipc::{send, socket_path, Request, Response};
+use std::{
+    fs,
+    os::unix::fs::PermissionsExt,
+    path::Path,
+    process::{Command, Output},
+    thread,
+    time::{Duration, Instant},
+};
+
+fn sbd(bin: &Path, db: &Path, log: &Path) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_sbd"))
+        .env("PATH", bin)
+        .env("DISPLAY", ":9")
+        .env_remove("WAYLAND_DISPLAY")
+        .arg("--db")
+        .arg(db)
+        .arg("--log")
+        .arg(log)
+        .arg("-d")
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn detach() {
+    let dir = config::ScratchDir::new("detach");
+    let bin = dir.join("bin");
+    fs::create_dir(&bin).unwrap();
+    let xsel = bin.join("xsel");
+    fs::write(&xsel, "#!/bin/sh\necho hello\n").unwrap();
+    fs::set_permissions(&xsel, fs::Permissions::from_mode(0o755)).unwrap();
+    let (db, log) = (dir.join("smash.db"), dir.join("sbd.log"));
+
+    let first = sbd(&bin, &db, &log);
+    assert!(first.status.success());
+    assert!(String::from_utf8_lossy(&first.stdout).starts_with("sbd is running as"));
+    // it is listening by the time the parent is gone
+    let pid = match send(&socket_path(&db), Request::Status) {
+        Ok(Response::Status(status)) => status.pid,
+        other => panic!("status answered {other:?}"),
+    };
+
+    let second = sbd(&bin, &db, &log);
+    assert!(!second.status.success());
+    let stderr = String::from_utf8_lossy(&second.stderr);
+    assert!(
+        stderr.contains(&format!("sbd is already running as {pid}")),
+        "{stderr}"
+    );
+
+    Command::new("kill").arg(pid.to_string()).status().unwrap();
+    let start = Instant::now();
+    while socket_path(&db).exists() {
+        assert!(
+            start.elapsed() < Duration::from_secs(10),
+            "sbd did not stop"
+        );
+        thread::sleep(Duration::from_millis(50));
+    }
+}
@@ -0,0 +1,273 @@
+use crate::{
+    watch::{self, Changes},
+    ClipboardError, Content, Selection, TEXT_MIME,
+};
+use std::{
+    collections::HashMap,
+    fs,
+    io::Write,
+    path::PathBuf,
+    process::{Command, Stdio},
+    sync::{Arc, Mutex},
+};
+
+// Whatever actually talks to the clipboard, Clipboard only decides what to
+// ask for and when
+pub trait ClipboardBackend {
+    // recorded as the source of every paste read through it
+    fn name(&self) -> &'static str;
+    // list of the MIME types (or X11 targets) the current owner is offering
+    fn offered_types(&self, selection: Selection) -> Result<Vec<String>, ClipboardError>;
+    // None asks for text in whatever encoding the tool defaults to
+    fn read(&self, selection: Selection, mime: Option<&str>) -> Result<Vec<u8>, ClipboardError>;
+    fn write(
+        &mut self,
+        selection: Selection,
+        bytes: &[u8],
+        mime: &str,
+    ) -> Result<(), ClipboardError>;
+    // None when the tool cannot tell about changes and has to be polled
+    fn changes(&self, _: Selection) -> Option<Changes> {
+        None
+    }
+}
+
+impl<B: ClipboardBackend + ?Sized> ClipboardBackend for Box<B> {
+    fn name(&self) -> &'static str {
+        (**self).name()
+    }
+    fn offered_types(&self, selection: Selection) -> Result<Vec<String>, ClipboardError> {
+        (**self).offered_types(selection)
+    }
+    fn read(&self, selection: Selection, mime: Option<&str>) -> Result<Vec<u8>, ClipboardError> {
+        (**self).read(selection, mime)
+    }
+    fn write(
+        &mut self,
+        selection: Selection,
+        bytes: &[u8],
+        mime: &str,
+    ) -> Result<(), ClipboardError> {
+        (**self).write(selection, bytes, mime)
+    }
+    fn changes(&self, selection: Selection) -> Option<Changes> {
+        (**self).changes(selection)
+    }
+}
+
+fn output(command: &mut Command) -> Result<Vec<u8>, ClipboardError> {
+    let output = command
+        .output()
+        .map_err(|_| ClipboardError::FailedToRunCommand)?;
+    if output.status.success() {
+        Ok(output.stdout)
+    } else {
+        Err(ClipboardError::CommandReturnedUnSuccesfully)
+    }
+}
+
+fn lines(output: Vec<u8>) -> Vec<String> {
+    String::from_utf8_lossy(&output)
+        .lines()
+        .map(|x| x.trim().to_string())
+        .filter(|x| !x.is_empty())
+        .collect()
+}
+
+// the tools fork and keep serving the content after we are gone, so their
+// output is not waited on
+fn feed(command: &mut Command, bytes: &[u8]) -> Result<(), ClipboardError> {
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|_| ClipboardError::FailedToRunCommand)?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin
+            .write_all(bytes)
+            .map_err(|_| ClipboardError::FailedToRunCommand)?;
+    }
+    match child.wait() {
+        Ok(status) if status.success() => Ok(()),
+        Ok(_) => Err(ClipboardError::CommandReturnedUnSuccesfully),
+        Err(_) => Err(ClipboardError::FailedToRunCommand),
+    }
+}
+
+// wl-paste and wl-copy from wl-clipboard
+pub struct WlClipboard;
+
+impl WlClipboard {
+    fn command(program: &str, selection: Selection) -> Command {
+        let mut command = Command::new(program);
+        if selection == Selection::Primary {
+            command.arg("--primary");
+        }
+        command
+    }
+}
+
+impl ClipboardBackend for WlClipboard {
+    fn name(&self) -> &'static str {
+        "wayland"
+    }
+    fn offered_types(&self, selection: Selection) -> Result<Vec<String>, ClipboardError> {
+        output(Self::command("wl-paste", selection).arg("--list-types")).map(lines)
+    }
+    fn read(&self, selection: Selection, mime: Option<&str>) -> Result<Vec<u8>, ClipboardError> {
+        let mut command = Self::command("wl-paste", selection);
+        if let Some(mime) = mime {
+            command.args(["--type", mime]);
+        }
+        output(&mut command)
+    }
+    fn write(
+        &mut self,
+        selection: Selection,
+        bytes: &[u8],
+        mime: &str,
+    ) -> Result<(), ClipboardError> {
+        feed(
+            Self::command("wl-copy", selection).args(["--type", mime]),
+            bytes,
+        )
+    }
+    fn changes(&self, selection: Selection) -> Option<Changes> {
+        watch::wl_paste(selection)
+    }
+}
+
+pub struct Xclip;
+
+impl ClipboardBackend for Xclip {
+    fn name(&self) -> &'static str {
+        "x11"
+    }
+    fn offered_types(&self, selection: Selection) -> Result<Vec<String>, ClipboardError> {
+        output(Command::new("xclip").args(["-o", "-selection", selection.name(), "-t", "TARGETS"]))
+            .map(lines)
+    }
+    fn read(&self, selection: Selection, mime: Option<&str>) -> Result<Vec<u8>, ClipboardError> {
+        let mut command = Command::new("xclip");
+        command.args(["-o", "-selection", selection.name()]);
+        if let Some(mime) = mime {
+            command.args(["-t", mime]);
+        }
+        output(&mut command)
+    }
+    fn write(
+        &mut self,
+        selection: Selection,
+        bytes: &[u8],
+        mime: &str,
+    ) -> Result<(), ClipboardError> {
+        let args = ["-i", "-selection", selection.name(), "-t", mime];
+        feed(Command::new("xclip").args(args), bytes)
+    }
+    fn changes(&self, selection: Selection) -> Option<Changes> {
+        watch::xfixes(selection)
+    }
+}
+
+// xsel only knows about text, so that is all it ever offers
+pub struct Xsel;
+
+impl ClipboardBackend for Xsel {
+    fn name(&self) -> &'static str {
+        "x11"
+    }
+    fn offered_types(&self, _: Selection) -> Result<Vec<String>, ClipboardError> {
+        Ok(vec![TEXT_MIME.to_string()])
+    }
+    fn read(&self, selection: Selection, _: Option<&str>) -> Result<Vec<u8>, ClipboardError> {
+        output(Command::new("xsel").args(["-o", &format!("--{}", selection.name())]))
+    }
+    fn write(
+        &mut self,
+        selection: Selection,
+        bytes: &[u8],
+        mime: &str,
+    ) -> Result<(), ClipboardError> {
+        if !mime.starts_with("text/") {
+            return Err(ClipboardError::UnsupportedMime(mime.to_string()));
+        }
+        let args = ["-i", &format!("--{}", selection.name())];
+        feed(Command::new("xsel").args(args), bytes)
+    }
+    fn changes(&self, selection: Selection) -> Option<Changes> {
+        watch::xfixes(selection)
+    }
+}
+
+// Stands in for a display server. In memory every clone sees the same
+// selections, in a directory every process pointed at it does.
+#[derive(Clone)]
+pub enum FakeClipboard {
+    Memory(Arc<Mutex<HashMap<Selection, Content>>>),
+    Dir(PathBuf),
+}
+
+impl FakeClipboard {
+    pub fn in_memory() -> Self {
+        FakeClipboard::Memory(Arc::default())
+    }
+
+    pub fn in_dir(dir: impl Into<PathBuf>) -> Self {
+        FakeClipboard::Dir(dir.into())
+    }
+
+    // an empty selection holds nothing and offers nothing
+    fn held(&self, selection: Selection) -> Option<Content> {
+        match self {
+            FakeClipboard::Memory(held) => held.lock().unwrap().get(&selection).cloned(),
+            FakeClipboard::Dir(dir) => {
+                let file = dir.join(selection.name());
+                let mime = fs::read_to_string(file.with_extension("mime")).ok()?;
+                let bytes = fs::read(file).ok()?;
+                Some(Content { bytes, mime })
+            }
+        }
+    }
+}
+
+impl ClipboardBackend for FakeClipboard {
+    fn name(&self) -> &'static str {
+        "fake"
+    }
+    fn offered_types(&self, selection: Selection) -> Result<Vec<String>, ClipboardError> {
+        Ok(self
+            .held(selection)
+            .map(|x| vec![x.mime])
+            .unwrap_or_default())
+    }
+    fn read(&self, selection: Selection, _: Option<&str>) -> Result<Vec<u8>, ClipboardError> {
+        self.held(selection)
+            .map(|x| x.bytes)
+            .ok_or(ClipboardError::NothingOffered)
+    }
+    fn write(
+        &mut self,
+        selection: Selection,
+        bytes: &[u8],
+        mime: &str,
+    ) -> Result<(), ClipboardError> {
+        let content = Content {
+            bytes: bytes.to_vec(),
+            mime: mime.to_string(),
+        };
+        match self {
+            FakeClipboard::Memory(held) => {
+                held.lock().unwrap().insert(selection, content);
+                Ok(())
+            }
+            FakeClipboard::Dir(dir) => {
+                let file = dir.join(selection.name());
+                fs::create_dir_all(&*dir)
+                    .and_then(|_| fs::write(&file, bytes))
+                    .and_then(|_| fs::write(file.with_extension("mime"), mime))
+                    .map_err(|_| ClipboardError::CommandReturnedUnSuccesfully)
+            }
+        }
+    }
+}
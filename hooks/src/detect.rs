@@ -0,0 +1,176 @@
+use crate::{ClipboardBackend, Environment, WlClipboard, Xclip, Xsel};
+use config::ClipboardTool;
+use std::{env, fmt, path::Path};
+
+// The backend that will be used along with why it was picked
+pub struct Detected {
+    pub backend: Box<dyn ClipboardBackend + Send>,
+    pub tool: ClipboardTool,
+    // None when the tool was set in the config
+    pub environment: Option<Environment>,
+    pub reason: String,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum DetectError {
+    // nothing says there is a display server at all
+    NoSession,
+    // the reason the tools were tried and none of them is installed
+    NotInstalled {
+        reason: String,
+        tried: Vec<ClipboardTool>,
+    },
+}
+
+impl fmt::Display for DetectError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DetectError::NoSession => write!(
+                f,
+                "no display server was found, WAYLAND_DISPLAY, DISPLAY and XDG_SESSION_TYPE are all unset"
+            ),
+            DetectError::NotInstalled { reason, tried } => {
+                let tried: Vec<_> = tried.iter().map(|x| x.to_string()).collect();
+                write!(f, "{reason} but {} is not installed", tried.join(" or "))
+            }
+        }
+    }
+}
+
+impl std::error::Error for DetectError {}
+
+// Picks a backend from the session the process runs in, unless the config
+// names one. A tool is only picked if its program is on the PATH.
+pub fn detect(tool: ClipboardTool) -> Result<Detected, DetectError> {
+    detect_from(tool, |name| env::var(name).ok(), on_path)
+}
+
+fn on_path(program: &str) -> bool {
+    env::var_os("PATH").is_some_and(|path| {
+        env::split_paths(&path).any(|dir| Path::new(&dir).join(program).is_file())
+    })
+}
+
+fn program(tool: ClipboardTool) -> &'static str {
+    match tool {
+        ClipboardTool::WlClipboard | ClipboardTool::Auto => "wl-paste",
+        ClipboardTool::Xclip => "xclip",
+        ClipboardTool::Xsel => "xsel",
+    }
+}
+
+fn backend(tool: ClipboardTool) -> Box<dyn ClipboardBackend + Send> {
+    match tool {
+        ClipboardTool::WlClipboard | ClipboardTool::Auto => Box::new(WlClipboard),
+        ClipboardTool::Xclip => Box::new(Xclip),
+        ClipboardTool::Xsel => Box::new(Xsel),
+    }
+}
+
+fn detect_from(
+    tool: ClipboardTool,
+    var: impl Fn(&str) -> Option<String>,
+    installed: impl Fn(&str) -> bool,
+) -> Result<Detected, DetectError> {
+    let set = |name: &str| var(name).is_some_and(|x| !x.is_empty());
+    let (environment, reason, candidates) = if tool != ClipboardTool::Auto {
+        (None, format!("{tool} was set in the config"), vec![tool])
+    } else if set("WAYLAND_DISPLAY") {
+        let mut candidates = vec![ClipboardTool::WlClipboard];
+        // XWayland serves the X11 tools the same clipboard
+        if set("DISPLAY") {
+            candidates.extend([ClipboardTool::Xclip, ClipboardTool::Xsel]);
+        }
+        let reason = "WAYLAND_DISPLAY is set".to_string();
+        (Some(Environment::Wayland), reason, candidates)
+    } else if set("DISPLAY") {
+        let reason = "DISPLAY is set".to_string();
+        let candidates = vec![ClipboardTool::Xclip, ClipboardTool::Xsel];
+        (Some(Environment::X11), reason, candidates)
+    } else {
+        match var("XDG_SESSION_TYPE").as_deref() {
+            Some("wayland") => (
+                Some(Environment::Wayland),
+                "XDG_SESSION_TYPE is wayland".to_string(),
+                vec![ClipboardTool::WlClipboard],
+            ),
+            Some("x11") => (
+                Some(Environment::X11),
+                "XDG_SESSION_TYPE is x11".to_string(),
+                vec![ClipboardTool::Xclip, ClipboardTool::Xsel],
+            ),
+            _ => return Err(DetectError::NoSession),
+        }
+    };
+    let Some(position) = candidates.iter().position(|x| installed(program(*x))) else {
+        return Err(DetectError::NotInstalled {
+            reason,
+            tried: candidates,
+        });
+    };
+    let tool = candidates[position];
+    let reason = match &candidates[..position] {
+        [] => reason,
+        skipped => {
+            let skipped: Vec<_> = skipped.iter().map(|x| x.to_string()).collect();
+            format!("{reason} and {} is not installed", skipped.join(" or "))
+        }
+    };
+    Ok(Detected {
+        backend: backend(tool),
+        tool,
+        environment,
+        reason,
+    })
+}
+
+#[test]
+fn test_detect() {
+    let detect = |tool, vars: &[(&str, &str)], installed: &[&str]| {
+        let var = |name: &str| {
+            vars.iter()
+                .find(|(x, _)| *x == name)
+                .map(|(_, value)| value.to_string())
+        };
+        detect_from(tool, var, |program| installed.contains(&program))
+            .map(|x| (x.tool, x.environment, x.reason))
+    };
+    let all = ["wl-paste", "xclip", "xsel"];
+    let both = [("WAYLAND_DISPLAY", "wayland-0"), ("DISPLAY", ":0")];
+
+    assert_eq!(
+        detect(ClipboardTool::Auto, &both, &all),
+        Ok((
+            ClipboardTool::WlClipboard,
+            Some(Environment::Wayland),
+            "WAYLAND_DISPLAY is set".to_string()
+        ))
+    );
+    assert_eq!(
+        detect(ClipboardTool::Auto, &both, &["xsel"]),
+        Ok((
+            ClipboardTool::Xsel,
+            Some(Environment::Wayland),
+            "WAYLAND_DISPLAY is set and wl-clipboard or xclip is not installed".to_string()
+        ))
+    );
+    assert_eq!(
+        detect(ClipboardTool::Auto, &[("XDG_SESSION_TYPE", "x11")], &all).map(|x| x.0),
+        Ok(ClipboardTool::Xclip)
+    );
+    // the config wins over the session
+    assert_eq!(
+        detect(ClipboardTool::Xsel, &both, &all).map(|x| x.0),
+        Ok(ClipboardTool::Xsel)
+    );
+
+    assert_eq!(
+        detect(ClipboardTool::Auto, &[], &all).map(|x| x.0),
+        Err(DetectError::NoSession)
+    );
+    let missing = detect(ClipboardTool::Auto, &[("DISPLAY", ":0")], &["wl-paste"]).map(|x| x.0);
+    assert_eq!(
+        missing.unwrap_err().to_string(),
+        "DISPLAY is set but xclip or xsel is not installed"
+    );
+}
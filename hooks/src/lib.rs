@@ -1,69 +1,272 @@
+mod backend;
+mod detect;
+mod watch;
+
+pub use backend::{ClipboardBackend, FakeClipboard, WlClipboard, Xclip, Xsel};
+pub use detect::{detect, DetectError, Detected};
+pub use watch::Changes;
+
 use std::{
-    process::Command,
+    fmt,
+    sync::mpsc::{self, Receiver, Sender},
     thread::{self},
     time::Duration,
 };
 
+// The kind of display server the session runs on
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum Environment {
     Wayland,
     X11,
 }
 
+// X11 has a primary selection next to the clipboard and so do most Wayland
+// compositors, the primary one holds whatever was selected last
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Selection {
+    #[default]
+    Clipboard,
+    Primary,
+}
+
+impl Selection {
+    fn name(&self) -> &'static str {
+        match self {
+            Selection::Clipboard => "clipboard",
+            Selection::Primary => "primary",
+        }
+    }
+}
+
 #[derive(Debug)]
 pub enum ClipboardError {
     FailedToRunCommand,
     CommandReturnedUnSuccesfully,
+    NothingOffered,
+    // the backend cannot hold this type at all
+    UnsupportedMime(String),
+}
+
+impl fmt::Display for ClipboardError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClipboardError::FailedToRunCommand => write!(f, "the clipboard tool could not be run"),
+            ClipboardError::CommandReturnedUnSuccesfully => {
+                write!(f, "the clipboard tool exited with an error")
+            }
+            ClipboardError::NothingOffered => write!(f, "the clipboard offers nothing usable"),
+            ClipboardError::UnsupportedMime(mime) => {
+                write!(f, "the clipboard tool cannot hold {mime}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ClipboardError {}
+
+// What the clipboard holds right now along with the type it was offered as
+#[derive(Debug, Clone, PartialEq)]
+pub struct Content {
+    pub bytes: Vec<u8>,
+    pub mime: String,
 }
 
-pub struct Clipboard {
-    environment: Environment,
-    previous: Vec<u8>,
+pub const TEXT_MIME: &str = "text/plain";
+
+pub struct Clipboard<B = Box<dyn ClipboardBackend + Send>> {
+    backend: B,
+    selection: Selection,
+    previous: Content,
     polling_rate: u64,
 }
 
-impl Clipboard {
-    pub fn new(environment: Environment, polling_rate: u64) -> Self {
+impl<B: ClipboardBackend> Clipboard<B> {
+    pub fn new(backend: B, polling_rate: u64) -> Self {
         Self {
-            environment,
+            backend,
+            selection: Selection::default(),
             polling_rate,
-            previous: Vec::new(),
+            previous: Content {
+                bytes: Vec::new(),
+                mime: String::from(TEXT_MIME),
+            },
         }
     }
 
-    pub fn poll(&mut self) -> Result<Vec<u8>, ClipboardError> {
+    // both reading and writing go to this selection
+    pub fn with_selection(mut self, selection: Selection) -> Self {
+        self.selection = selection;
+        self
+    }
+
+    // how pastes read from here are recorded
+    pub fn source(&self) -> &'static str {
+        self.backend.name()
+    }
+
+    pub fn selection(&self) -> Selection {
+        self.selection
+    }
+
+    pub fn poll(&mut self) -> Result<Content, ClipboardError> {
         loop {
             thread::sleep(Duration::from_secs(self.polling_rate));
             let paste = self.get_clipboard()?;
-            if !are_equal(&self.previous, &paste) {
+            if !are_equal(&self.previous.bytes, &paste.bytes) || self.previous.mime != paste.mime {
                 self.previous = paste.clone();
                 return Ok(paste);
             }
         }
     }
 
-    fn get_clipboard(&self) -> Result<Vec<u8>, ClipboardError> {
-        let output = match self.environment {
-            Environment::Wayland => Command::new("wl-paste")
-                .output()
-                .map_err(|_| ClipboardError::FailedToRunCommand)?,
-            Environment::X11 => Command::new("xclip")
-                .arg("-o")
-                .output()
-                .map_err(|_| ClipboardError::FailedToRunCommand)?,
-        };
-        // Make this better
-        if output.status.success() {
-            Ok(output.stdout)
-        } else {
-            Err(ClipboardError::CommandReturnedUnSuccesfully)
+    // Every change of the selection as it happens. The clipboard moves to a
+    // thread that waits for the tool to report changes, or polls every
+    // polling_rate seconds when it cannot. The thread is done once the
+    // receiver is dropped and the next change comes in.
+    pub fn watch(mut self) -> Receiver<Result<Content, ClipboardError>>
+    where
+        B: Send + 'static,
+    {
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            if let Some(changes) = self.backend.changes(self.selection) {
+                for () in changes {
+                    if !self.forward(&sender) {
+                        return;
+                    }
+                }
+            }
+            // the watcher died or there never was one
+            loop {
+                thread::sleep(Duration::from_secs(self.polling_rate));
+                if !self.forward(&sender) {
+                    return;
+                }
+            }
+        });
+        receiver
+    }
+
+    // hands on whatever is new, false once nobody is listening anymore
+    fn forward(&mut self, sender: &Sender<Result<Content, ClipboardError>>) -> bool {
+        match self.get_clipboard() {
+            Ok(paste) if paste == self.previous => true,
+            Ok(paste) => {
+                self.previous = paste.clone();
+                sender.send(Ok(paste)).is_ok()
+            }
+            // an empty clipboard is nothing to report
+            Err(ClipboardError::NothingOffered) => true,
+            Err(e) => sender.send(Err(e)).is_ok(),
         }
     }
+
+    pub fn get_clipboard(&self) -> Result<Content, ClipboardError> {
+        let offered = self.backend.offered_types(self.selection)?;
+        let mime = preferred_mime(&offered).ok_or(ClipboardError::NothingOffered)?;
+        // text is asked for in whatever encoding the tool defaults to
+        let requested = if mime == TEXT_MIME {
+            None
+        } else {
+            Some(mime.as_str())
+        };
+        let bytes = self.backend.read(self.selection, requested)?;
+        Ok(Content { bytes, mime })
+    }
+
+    pub fn set_clipboard(&mut self, bytes: Vec<u8>, mime: &str) -> Result<(), ClipboardError> {
+        self.backend.write(self.selection, &bytes, mime)?;
+        // polling should not hand back what was just written
+        self.previous = Content {
+            bytes,
+            mime: mime.to_string(),
+        };
+        Ok(())
+    }
+}
+
+// Text wins whenever it is offered, otherwise images and then whatever
+// real MIME type shows up first. X11 only atoms like TARGETS are skipped.
+fn preferred_mime(types: &[String]) -> Option<String> {
+    let textual = [
+        "text/plain;charset=utf-8",
+        "UTF8_STRING",
+        "text/plain",
+        "STRING",
+        "TEXT",
+    ];
+    if types.iter().any(|x| textual.contains(&x.as_str())) {
+        return Some(String::from(TEXT_MIME));
+    }
+    types
+        .iter()
+        .find(|x| x.starts_with("image/"))
+        .or_else(|| types.iter().find(|x| x.contains('/')))
+        .cloned()
 }
 
 #[test]
 fn test_get_clipboard() {
-    let mut clip = Clipboard::new(Environment::Wayland, 2);
-    _ = clip.poll();
+    let fake = FakeClipboard::in_memory();
+    let mut clip = Clipboard::new(fake.clone(), 0);
+    assert!(matches!(
+        clip.get_clipboard(),
+        Err(ClipboardError::NothingOffered)
+    ));
+    // someone else copies something
+    let mut other = Clipboard::new(fake, 0);
+    other.set_clipboard(b"hello".to_vec(), TEXT_MIME).unwrap();
+    let content = clip.poll().unwrap();
+    assert_eq!(content.bytes, b"hello");
+    assert_eq!(clip.source(), "fake");
+}
+
+#[test]
+fn test_fake_clipboard() {
+    let dir = config::ScratchDir::new("fake-clipboard");
+    let mut writer = Clipboard::new(FakeClipboard::in_dir(dir.path()), 0);
+    let mut reader = Clipboard::new(FakeClipboard::in_dir(dir.path()), 0);
+    writer.set_clipboard(b"hello".to_vec(), TEXT_MIME).unwrap();
+    let content = reader.poll().unwrap();
+    assert_eq!(content.bytes, b"hello");
+    assert_eq!(content.mime, TEXT_MIME);
+
+    // the primary selection is kept apart
+    let fake = FakeClipboard::in_dir(dir.path());
+    let mut primary = Clipboard::new(fake, 0).with_selection(Selection::Primary);
+    primary.set_clipboard(vec![0x89], "image/png").unwrap();
+    assert_eq!(primary.get_clipboard().unwrap().mime, "image/png");
+    assert_eq!(reader.get_clipboard().unwrap(), content);
+}
+
+#[test]
+fn test_watch() {
+    let fake = FakeClipboard::in_memory();
+    let mut other = Clipboard::new(fake.clone(), 0);
+    // the fake cannot report changes, so this is the polling fallback
+    let changes = Clipboard::new(fake, 1).watch();
+    other.set_clipboard(b"first".to_vec(), TEXT_MIME).unwrap();
+    assert_eq!(changes.recv().unwrap().unwrap().bytes, b"first");
+    other.set_clipboard(b"second".to_vec(), TEXT_MIME).unwrap();
+    assert_eq!(changes.recv().unwrap().unwrap().bytes, b"second");
+}
+
+#[test]
+fn test_preferred_mime() {
+    let offer = |x: &[&str]| preferred_mime(&x.iter().map(|x| x.to_string()).collect::<Vec<_>>());
+    assert_eq!(
+        offer(&["image/png", "UTF8_STRING"]),
+        Some(TEXT_MIME.to_string())
+    );
+    assert_eq!(
+        offer(&["TARGETS", "text/html", "image/png"]),
+        Some("image/png".to_string())
+    );
+    assert_eq!(
+        offer(&["TARGETS", "text/html"]),
+        Some("text/html".to_string())
+    );
+    assert_eq!(offer(&["TARGETS"]), None);
 }
 
 fn are_equal(a: &[u8], b: &[u8]) -> bool {
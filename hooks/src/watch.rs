@@ -0,0 +1,91 @@
+use crate::Selection;
+use std::{
+    io::{BufRead, BufReader, Lines},
+    process::{Child, ChildStdout, Command, Stdio},
+};
+
+// Blocks until the selection changes, one item per change. The content
+// that is already there counts as the first change.
+pub type Changes = Box<dyn Iterator<Item = ()> + Send>;
+
+// wl-paste runs echo for every change, so every line it prints is one
+struct Watched {
+    child: Child,
+    lines: Lines<BufReader<ChildStdout>>,
+}
+
+impl Iterator for Watched {
+    type Item = ();
+
+    fn next(&mut self) -> Option<()> {
+        self.lines.next()?.ok().map(|_| ())
+    }
+}
+
+impl Drop for Watched {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+pub(crate) fn wl_paste(selection: Selection) -> Option<Changes> {
+    let mut command = Command::new("wl-paste");
+    if selection == Selection::Primary {
+        command.arg("--primary");
+    }
+    let mut child = command
+        .args(["--watch", "echo"])
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .ok()?;
+    let lines = BufReader::new(child.stdout.take()?).lines();
+    Some(Box::new(Watched { child, lines }))
+}
+
+// XFixes tells every client that asks when a selection gets a new owner
+#[cfg(feature = "x11")]
+pub(crate) fn xfixes(selection: Selection) -> Option<Changes> {
+    use x11rb::{
+        connection::Connection,
+        protocol::{
+            xfixes::{ConnectionExt as _, SelectionEventMask},
+            xproto::{AtomEnum, ConnectionExt as _},
+            Event,
+        },
+    };
+
+    let (conn, screen) = x11rb::connect(None).ok()?;
+    let root = conn.setup().roots[screen].root;
+    let atom = match selection {
+        Selection::Primary => AtomEnum::PRIMARY.into(),
+        Selection::Clipboard => {
+            conn.intern_atom(false, b"CLIPBOARD")
+                .ok()?
+                .reply()
+                .ok()?
+                .atom
+        }
+    };
+    conn.xfixes_query_version(5, 0).ok()?.reply().ok()?;
+    let mask = SelectionEventMask::SET_SELECTION_OWNER
+        | SelectionEventMask::SELECTION_WINDOW_DESTROY
+        | SelectionEventMask::SELECTION_CLIENT_CLOSE;
+    conn.xfixes_select_selection_input(root, atom, mask).ok()?;
+    conn.flush().ok()?;
+    let changes = std::iter::from_fn(move || loop {
+        match conn.wait_for_event() {
+            Ok(Event::XfixesSelectionNotify(_)) => return Some(()),
+            Ok(_) => continue,
+            Err(_) => return None,
+        }
+    });
+    Some(Box::new(std::iter::once(()).chain(changes)))
+}
+
+#[cfg(not(feature = "x11"))]
+pub(crate) fn xfixes(_: Selection) -> Option<Changes> {
+    None
+}
@@ -0,0 +1,336 @@
+// The control socket between sb and sbd. Every connection carries one
+// request line and gets one response line back.
+use std::{
+    fmt,
+    fs::{self, Permissions},
+    io::{self, BufRead, BufReader, Write},
+    os::unix::{
+        fs::PermissionsExt,
+        net::{UnixListener, UnixStream},
+    },
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Receiver, Sender},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+// a client that connects and then says nothing is not waited on forever
+const TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Request {
+    Status,
+    // stop storing what is copied until resumed, or until the time is up
+    Pause(Option<Duration>),
+    Resume,
+    // store what the clipboard holds right now
+    Snapshot,
+    ReloadConfig,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Status {
+    pub pid: u32,
+    pub paused: bool,
+    // left of a timed pause
+    pub resumes_in: Option<Duration>,
+    // entries stored since the daemon started
+    pub captured: usize,
+    // copies that were not stored because capture was paused
+    pub skipped: usize,
+    // the clipboard backend that is watched
+    pub source: String,
+    pub database: PathBuf,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Response {
+    Ok,
+    Status(Status),
+    Error(String),
+}
+
+#[derive(Debug)]
+pub enum IpcError {
+    // nobody is listening on the socket
+    NotRunning,
+    Io(io::Error),
+    // the other side said something that is not part of the protocol
+    Garbled(String),
+}
+
+impl fmt::Display for IpcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IpcError::NotRunning => write!(f, "sbd is not running"),
+            IpcError::Io(e) => write!(f, "talking to sbd failed: {e}"),
+            IpcError::Garbled(line) => write!(f, "sbd answered {line:?}"),
+        }
+    }
+}
+
+impl std::error::Error for IpcError {}
+
+impl fmt::Display for Request {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Request::Status => write!(f, "status"),
+            Request::Pause(None) => write!(f, "pause"),
+            Request::Pause(Some(time)) => write!(f, "pause {}", time.as_secs()),
+            Request::Resume => write!(f, "resume"),
+            Request::Snapshot => write!(f, "snapshot"),
+            Request::ReloadConfig => write!(f, "reload-config"),
+        }
+    }
+}
+
+impl FromStr for Request {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(' ').unwrap_or((s, "")) {
+            ("pause", "") => Ok(Request::Pause(None)),
+            ("pause", secs) => match secs.parse() {
+                Ok(secs) => Ok(Request::Pause(Some(Duration::from_secs(secs)))),
+                Err(_) => Err(format!("pause takes seconds, not {secs}")),
+            },
+            _ => Self::from_word(s),
+        }
+    }
+}
+
+impl Request {
+    fn from_word(s: &str) -> Result<Self, String> {
+        match s {
+            "status" => Ok(Request::Status),
+            "resume" => Ok(Request::Resume),
+            "snapshot" => Ok(Request::Snapshot),
+            "reload-config" => Ok(Request::ReloadConfig),
+            _ => Err(format!("unknown request {s}")),
+        }
+    }
+}
+
+// the database path comes last as it may hold spaces, a pause that is not
+// timed resumes in -
+impl fmt::Display for Response {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Response::Ok => write!(f, "ok"),
+            Response::Status(status) => write!(
+                f,
+                "status pid={} paused={} resumes_in={} captured={} skipped={} source={} database={}",
+                status.pid,
+                status.paused,
+                status
+                    .resumes_in
+                    .map_or("-".to_string(), |x| x.as_secs().to_string()),
+                status.captured,
+                status.skipped,
+                status.source,
+                status.database.display()
+            ),
+            Response::Error(message) => write!(f, "error {message}"),
+        }
+    }
+}
+
+impl FromStr for Response {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let garbled = || s.to_string();
+        match s.split_once(' ').unwrap_or((s, "")) {
+            ("ok", "") => Ok(Response::Ok),
+            ("error", message) => Ok(Response::Error(message.to_string())),
+            ("status", fields) => {
+                let (fields, database) = fields.split_once(" database=").ok_or_else(garbled)?;
+                let field = |name: &str| {
+                    fields
+                        .split(' ')
+                        .find_map(|x| x.strip_prefix(name)?.strip_prefix('='))
+                        .ok_or_else(garbled)
+                };
+                Ok(Response::Status(Status {
+                    pid: field("pid")?.parse().map_err(|_| garbled())?,
+                    paused: field("paused")?.parse().map_err(|_| garbled())?,
+                    resumes_in: match field("resumes_in")? {
+                        "-" => None,
+                        secs => Some(Duration::from_secs(secs.parse().map_err(|_| garbled())?)),
+                    },
+                    captured: field("captured")?.parse().map_err(|_| garbled())?,
+                    skipped: field("skipped")?.parse().map_err(|_| garbled())?,
+                    source: field("source")?.to_string(),
+                    database: PathBuf::from(database),
+                }))
+            }
+            _ => Err(garbled()),
+        }
+    }
+}
+
+// the socket lives next to the database, like the pid file
+pub fn socket_path(db_path: &Path) -> PathBuf {
+    db_path.with_extension("sock")
+}
+
+pub fn send(socket: &Path, request: Request) -> Result<Response, IpcError> {
+    let mut stream = UnixStream::connect(socket).map_err(|e| match e.kind() {
+        io::ErrorKind::NotFound | io::ErrorKind::ConnectionRefused => IpcError::NotRunning,
+        _ => IpcError::Io(e),
+    })?;
+    stream
+        .set_read_timeout(Some(TIMEOUT))
+        .map_err(IpcError::Io)?;
+    writeln!(stream, "{request}").map_err(IpcError::Io)?;
+    let mut line = String::new();
+    BufReader::new(stream)
+        .read_line(&mut line)
+        .map_err(IpcError::Io)?;
+    let line = line.trim_end();
+    line.parse()
+        .map_err(|_| IpcError::Garbled(line.to_string()))
+}
+
+type Pending = (Request, Sender<Response>);
+
+// Connections are read on threads of their own, so a client that says
+// nothing never holds up the daemon. Their requests wait until it gets to
+// them in serve.
+pub struct Server {
+    path: PathBuf,
+    requests: Receiver<Pending>,
+    stop: Arc<AtomicBool>,
+    accepting: Option<JoinHandle<()>>,
+}
+
+impl Server {
+    // a socket left behind by a daemon that died is replaced, the pid
+    // lock already makes sure no live one is using it
+    pub fn bind(path: &Path) -> io::Result<Self> {
+        match fs::remove_file(path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+        let listener = UnixListener::bind(path)?;
+        fs::set_permissions(path, Permissions::from_mode(0o600))?;
+        let (sender, requests) = mpsc::channel();
+        let stop = Arc::new(AtomicBool::new(false));
+        let accepting = {
+            let stop = Arc::clone(&stop);
+            thread::spawn(move || {
+                for stream in listener.incoming() {
+                    if stop.load(Ordering::Relaxed) {
+                        break;
+                    }
+                    if let Ok(stream) = stream {
+                        let sender = sender.clone();
+                        thread::spawn(move || answer(stream, &sender));
+                    }
+                }
+            })
+        };
+        Ok(Self {
+            path: path.to_path_buf(),
+            requests,
+            stop,
+            accepting: Some(accepting),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // answers every request that is already waiting
+    pub fn serve(&self, mut handle: impl FnMut(Request) -> Response) {
+        while let Ok((request, reply)) = self.requests.try_recv() {
+            let _ = reply.send(handle(request));
+        }
+    }
+}
+
+fn answer(stream: UnixStream, requests: &Sender<Pending>) -> io::Result<()> {
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    let mut line = String::new();
+    BufReader::new(&stream).read_line(&mut line)?;
+    let response = match line.trim_end().parse() {
+        Ok(request) => {
+            let (reply, response) = mpsc::channel();
+            // both fail once the server is gone
+            let _ = requests.send((request, reply));
+            response
+                .recv()
+                .unwrap_or_else(|_| Response::Error("sbd is stopping".to_string()))
+        }
+        Err(e) => Response::Error(e),
+    };
+    writeln!(&stream, "{response}")
+}
+
+// the accepting thread is woken up with a connection of our own to see it
+// has to stop
+impl Drop for Server {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        let _ = UnixStream::connect(&self.path);
+        if let Some(accepting) = self.accepting.take() {
+            let _ = accepting.join();
+        }
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+#[test]
+fn round_trip() {
+    let status = Response::Status(Status {
+        pid: 42,
+        paused: true,
+        resumes_in: Some(Duration::from_secs(60)),
+        captured: 3,
+        skipped: 1,
+        source: "wayland".to_string(),
+        database: PathBuf::from("/tmp/with space/smash.db"),
+    });
+    for response in [status, Response::Ok, Response::Error("no".to_string())] {
+        assert_eq!(response.to_string().parse(), Ok(response));
+    }
+    assert_eq!("reload-config".parse(), Ok(Request::ReloadConfig));
+    let timed = Request::Pause(Some(Duration::from_secs(300)));
+    assert_eq!(timed.to_string().parse(), Ok(timed));
+    assert!("pause soon".parse::<Request>().is_err());
+
+    let dir = config::ScratchDir::new("ipc");
+    let path = dir.join("smash.sock");
+    assert!(matches!(
+        send(&path, Request::Status),
+        Err(IpcError::NotRunning)
+    ));
+    let server = Server::bind(&path).unwrap();
+    let client = std::thread::spawn(move || send(&path, Request::Pause(None)));
+    let mut seen = None;
+    while seen.is_none() {
+        server.serve(|request| {
+            seen = Some(request);
+            Response::Ok
+        });
+    }
+    assert_eq!(seen, Some(Request::Pause(None)));
+    assert_eq!(client.join().unwrap().unwrap(), Response::Ok);
+
+    // a client that says nothing does not hold up the others
+    let _silent = UnixStream::connect(server.path()).unwrap();
+    let path = server.path().to_path_buf();
+    let start = std::time::Instant::now();
+    let client = std::thread::spawn(move || send(&path, Request::Status));
+    while !client.is_finished() {
+        server.serve(|_| Response::Ok);
+    }
+    assert!(start.elapsed() < TIMEOUT);
+    assert_eq!(client.join().unwrap().unwrap(), Response::Ok);
+}
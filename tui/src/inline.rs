@@ -1,31 +1,86 @@
-use std::io;
+use std::{
+    io::{self, IsTerminal, Write},
+    time::Duration,
+};
 
 use ratatui::{
     backend::CrosstermBackend,
-    layout::Constraint,
+    layout::{Constraint, Direction, Layout},
     style::{Color, Style},
     widgets::{Row, Table},
     Terminal, TerminalOptions, Viewport,
 };
 
+// A single line of the show table
+pub struct PreviewRow {
+    // how the buffer is addressed, like 0, @0 or :name
+    pub label: String,
+    // stable handle like #3f2a9c1, None for entries that have none
+    pub handle: Option<String>,
+    pub text: String,
+    // None when the capture time was never recorded
+    pub age: Option<Duration>,
+    pub size: usize,
+}
+
 // meant to be called with show command
-pub fn show_preview(buffers: Vec<(usize, String)>) {
-    let (lines, table) = make_table(buffers);
-    let mut l = 2;
-    l += lines;
+// every non empty section is drawn as its own table, one below the other,
+// or written out as plain rows when stdout is not a terminal
+pub fn show_preview(sections: Vec<(&str, Vec<PreviewRow>)>) {
+    if !io::stdout().is_terminal() {
+        let mut stdout = io::stdout().lock();
+        for row in sections.iter().flat_map(|(_, rows)| rows) {
+            // a reader that went away early is not an error
+            if writeln!(stdout, "{}", plain_row(row)).is_err() {
+                return;
+            }
+        }
+        return;
+    }
+    let tables: Vec<_> = sections
+        .into_iter()
+        .filter(|(_, rows)| !rows.is_empty())
+        // header and footer of each table
+        .map(|(title, rows)| {
+            let (lines, table) = make_table(rows, title);
+            (lines + 2, table)
+        })
+        .collect();
+    let l = tables.iter().map(|(lines, _)| lines).sum();
     let mut terminal = Terminal::with_options(
         CrosstermBackend::new(io::stdout()),
         TerminalOptions {
-            viewport: Viewport::Inline(l as u16),
+            viewport: Viewport::Inline(l),
         },
     )
     .unwrap();
 
     terminal
-        .draw(|frame| frame.render_widget(&table, frame.size()))
+        .draw(|frame| {
+            let layout = Layout::default()
+                .direction(Direction::Vertical)
+                .constraints(tables.iter().map(|(lines, _)| Constraint::Length(*lines)))
+                .split(frame.size());
+            for (area, (_, table)) in layout.iter().zip(&tables) {
+                frame.render_widget(table, *area);
+            }
+        })
         .unwrap();
 }
 
+// label, handle, age, size in bytes and text separated by tabs, the text
+// kept on one line so every row is one line
+fn plain_row(row: &PreviewRow) -> String {
+    format!(
+        "{}\t{}\t{}\t{}\t{}",
+        row.label,
+        row.handle.as_deref().unwrap_or("-"),
+        format_age(row.age),
+        row.size,
+        row.text.replace(['\n', '\t'], " ")
+    )
+}
+
 fn count_lines(blob: &str) -> u16 {
     let mut count = 1;
     for i in blob.chars() {
@@ -34,28 +89,73 @@ fn count_lines(blob: &str) -> u16 {
     count as u16
 }
 
-fn make_table<'a>(buffers: Vec<(usize, String)>) -> (u16, Table<'a>) {
+// largest unit only, 42s 5m 3h 2d
+fn format_age(age: Option<Duration>) -> String {
+    let Some(age) = age else {
+        return String::from("-");
+    };
+    let secs = age.as_secs();
+    if secs < 60 {
+        format!("{}s", secs)
+    } else if secs < 60 * 60 {
+        format!("{}m", secs / 60)
+    } else if secs < 60 * 60 * 24 {
+        format!("{}h", secs / (60 * 60))
+    } else {
+        format!("{}d", secs / (60 * 60 * 24))
+    }
+}
+
+fn format_size(size: usize) -> String {
+    let units = ["B", "K", "M", "G"];
+    let mut size = size as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < units.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{}{}", size, units[unit])
+    } else {
+        format!("{:.1}{}", size, units[unit])
+    }
+}
+
+fn make_table<'a>(buffers: Vec<PreviewRow>, title: &'a str) -> (u16, Table<'a>) {
     let mut color = true;
     let mut total_lines = 0;
     let rows = buffers
         .into_iter()
-        .map(|(i, st)| {
+        .map(|row| {
             color = !color;
-            let h = count_lines(&st);
+            let h = count_lines(&row.text);
             total_lines += h;
-            (i.to_string(), st, h, color)
+            (row, h, color)
         })
-        .map(|(i, st, h, color)| {
-            Row::new([i, st])
-                .height(h)
-                .style(Style::default().bg(if color {
-                    Color::DarkGray
-                } else {
-                    Color::default()
-                }))
+        .map(|(row, h, color)| {
+            Row::new([
+                row.label,
+                row.handle.unwrap_or_else(|| String::from("-")),
+                format_age(row.age),
+                format_size(row.size),
+                row.text,
+            ])
+            .height(h)
+            .style(Style::default().bg(if color {
+                Color::DarkGray
+            } else {
+                Color::default()
+            }))
         });
-    let header = Row::new(vec!["Id", "Buffers"]).style(Style::default().fg(Color::Green));
-    let constraints = [Constraint::Ratio(1, 11), Constraint::Ratio(10, 11)];
+    let header = Row::new(vec!["Id", "Handle", "Age", "Size", title])
+        .style(Style::default().fg(Color::Green));
+    let constraints = [
+        Constraint::Length(4),
+        Constraint::Length(8),
+        Constraint::Length(5),
+        Constraint::Length(7),
+        Constraint::Fill(1),
+    ];
     let table = Table::new(rows, constraints)
         .widths(constraints)
         .header(header.clone())
@@ -72,10 +172,17 @@ fn t2() {
     ]
     .into_iter()
     .enumerate()
+    .map(|(index, text)| PreviewRow {
+        label: index.to_string(),
+        handle: Some(format!("#{:07x}", index * 0x1f2e3d)),
+        size: text.len(),
+        age: Some(Duration::from_secs(index as u64 * 4000)),
+        text,
+    })
     .collect();
 
     let mut l = rows.len() as u16 + 2;
-    let (lines, table) = make_table(rows);
+    let (lines, table) = make_table(rows, "Buffers");
     l += lines;
 
     let mut terminal = Terminal::with_options(
@@ -90,3 +197,25 @@ fn t2() {
         .draw(|frame| frame.render_widget(&table, frame.size()))
         .unwrap();
 }
+
+#[test]
+fn age_and_size() {
+    assert_eq!(format_age(None), "-");
+    assert_eq!(format_age(Some(Duration::from_secs(42))), "42s");
+    assert_eq!(format_age(Some(Duration::from_secs(3 * 60 * 60 + 5))), "3h");
+    assert_eq!(format_size(512), "512B");
+    assert_eq!(format_size(1536), "1.5K");
+    assert_eq!(format_size(3 * 1024 * 1024), "3.0M");
+}
+
+#[test]
+fn plain_rows() {
+    let row = PreviewRow {
+        label: ":todo".to_string(),
+        handle: None,
+        text: "buy\tmilk\nand eggs".to_string(),
+        age: Some(Duration::from_secs(300)),
+        size: 2048,
+    };
+    assert_eq!(plain_row(&row), ":todo\t-\t5m\t2048\tbuy milk and eggs");
+}
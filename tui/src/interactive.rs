@@ -1,23 +1,33 @@
 use crate::widgets::{Preview, PromptText, ShuffleOperation};
-use config::Base;
+use config::{Base, Join};
 
-use std::io::{self, stdout};
+use std::{
+    io::{self, stdout},
+    ops::Range,
+};
 
 use crossterm::{
-    event::{self, Event, KeyCode},
+    event::{self, Event, KeyCode, KeyModifiers},
     queue,
     terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
 };
 use ratatui::{
     backend::CrosstermBackend,
     layout::{Constraint, Direction, Layout},
+    style::{Style, Stylize},
+    text::{Line, Span, Text},
     widgets::{Block, Borders, Paragraph},
     Frame, Terminal,
 };
 
 enum Operation {
     Exit,
+    // same as Exit but the result goes on the clipboard
+    ExitToClipboard,
     Abort,
+    // move on to the next way of joining the selection
+    NextJoin,
+    #[allow(dead_code)]
     ExitError,
     Waiting,
 }
@@ -28,10 +38,17 @@ fn handle_events(
     if event::poll(std::time::Duration::from_millis(50))? {
         if let Event::Key(key) = event::read()? {
             if key.kind == event::KeyEventKind::Press {
-                if KeyCode::Enter == key.code {
+                if key.modifiers.contains(KeyModifiers::CONTROL) {
+                    if KeyCode::Char('y') == key.code {
+                        return Ok((Some(Operation::ExitToClipboard), None));
+                    }
+                    return Ok((None, None));
+                } else if KeyCode::Enter == key.code {
                     return Ok((Some(Operation::Exit), None));
                 } else if KeyCode::Esc == key.code {
                     return Ok((Some(Operation::Abort), None));
+                } else if KeyCode::Tab == key.code {
+                    return Ok((Some(Operation::NextJoin), None));
                 } else {
                     if let KeyCode::Char(x) = key.code {
                         return Ok((None, text.push(x)));
@@ -48,8 +65,44 @@ fn handle_events(
     Ok((Some(Operation::Waiting), None))
 }
 
+// What was put together in compose_ui
+pub struct Composition {
+    // the prompt as it was typed, a sequence check has nothing against,
+    // the highlighted buffers are only there to look at
+    pub input: String,
+    pub join: Join,
+    // Ctrl+Y was used instead of Enter
+    pub to_clipboard: bool,
+}
+
 // The main function for in this module
-pub fn compose_ui(base: Base, blobs: Vec<String>) -> io::Result<()> {
+// check gets the prompt input and returns where it stops making sense and why,
+// a note (like capture being paused) is shown in the prompt header, and
+// nothing comes back when it was aborted with Esc
+// load gets the previews of the stack (or the pinned list) in a range of
+// indices, for when a bracketed index goes past blobs (or pinned)
+pub fn compose_ui(
+    base: Base,
+    blobs: Vec<String>,
+    pinned: Vec<String>,
+    load: impl Fn(bool, Range<usize>) -> Vec<String>,
+    check: impl Fn(&str) -> Option<(usize, String)>,
+    join: Join,
+    note: Option<&str>,
+) -> io::Result<Option<Composition>> {
+    // a custom join can only come from outside, so it is kept in the cycle
+    let mut joins = vec![
+        Join::Newline,
+        Join::None,
+        Join::Space,
+        Join::Trim,
+        Join::Smart,
+    ];
+    if !joins.contains(&join) {
+        joins.push(join.clone());
+    }
+    let mut current = joins.iter().position(|x| *x == join).unwrap_or(0);
+
     // init for terminal
     queue!(stdout(), EnterAlternateScreen)?;
     enable_raw_mode()?;
@@ -57,7 +110,13 @@ pub fn compose_ui(base: Base, blobs: Vec<String>) -> io::Result<()> {
 
     // setup widget
     let mut prompt_string = PromptText::new(base);
-    let mut buffers = Preview::new(blobs);
+    let mut buffers = Preview::new(blobs, pinned);
+    let reach = |buffers: &mut Preview, pinned: bool, n: usize| {
+        let loaded = buffers.loaded(pinned);
+        if n >= loaded {
+            buffers.load(pinned, load(pinned, loaded..n + 1));
+        }
+    };
 
     let exit_status: Operation = loop {
         // event
@@ -65,21 +124,45 @@ pub fn compose_ui(base: Base, blobs: Vec<String>) -> io::Result<()> {
         match to_quit {
             None => {}
             Some(op) => match op {
-                Operation::Exit | Operation::ExitError | Operation::Abort => {
+                // the error is already showing in the prompt
+                Operation::Exit | Operation::ExitToClipboard
+                    if check(prompt_string.return_input()).is_some() => {}
+                Operation::Exit
+                | Operation::ExitToClipboard
+                | Operation::ExitError
+                | Operation::Abort => {
                     break op;
                 }
+                Operation::NextJoin => current = (current + 1) % joins.len(),
                 Operation::Waiting => {}
             },
         }
-        match operation_for_list {
-            Some(op) => match op {
+        if let Some(op) = operation_for_list {
+            match op {
                 ShuffleOperation::Pop(n) => buffers.unselect(n),
-                ShuffleOperation::Push(n) => buffers.select(n),
-            },
-            None => {}
+                ShuffleOperation::Push(n) => {
+                    reach(&mut buffers, false, n);
+                    buffers.select(n)
+                }
+                ShuffleOperation::PopPinned(n) => buffers.unselect_pinned(n),
+                ShuffleOperation::PushPinned(n) => {
+                    reach(&mut buffers, true, n);
+                    buffers.select_pinned(n)
+                }
+            }
         }
         // render
-        terminal.draw(|frame| layout_and_render(frame, &prompt_string, &buffers))?;
+        let error = check(prompt_string.return_input());
+        terminal.draw(|frame| {
+            layout_and_render(
+                frame,
+                &prompt_string,
+                &error,
+                &joins[current],
+                note,
+                &buffers,
+            )
+        })?;
     };
     // deinit for terminal
     disable_raw_mode()?;
@@ -87,16 +170,14 @@ pub fn compose_ui(base: Base, blobs: Vec<String>) -> io::Result<()> {
 
     // exract out from buffer
     match exit_status {
-        Operation::Exit => buffers.yeild_list().into_iter().for_each(|lines| {
-            for line in lines {
-                print!("{}", line);
-            }
-            println!();
-        }),
-        Operation::Abort | Operation::ExitError => {}
-        Operation::Waiting => unreachable!(),
+        Operation::Exit | Operation::ExitToClipboard => Ok(Some(Composition {
+            input: prompt_string.return_input().to_string(),
+            join: joins.swap_remove(current),
+            to_clipboard: matches!(exit_status, Operation::ExitToClipboard),
+        })),
+        Operation::Abort | Operation::ExitError => Ok(None),
+        Operation::Waiting | Operation::NextJoin => unreachable!(),
     }
-    Ok(())
 }
 
 #[test]
@@ -113,12 +194,28 @@ fn t1() {
             "Hello world".to_string(),
             "Goodbye world".to_string(),
         ],
+        vec!["Pinned world".to_string()],
+        |_, range| range.map(|x| format!("Deep world {x}")).collect(),
+        |input| {
+            input
+                .find('9')
+                .map(|x| (x, "index 9 is not valid".to_string()))
+        },
+        Join::Custom(", ".to_string()),
+        Some("capture paused"),
     );
 }
 
 // the main frame
-fn layout_and_render<'a>(frame: &mut Frame, prompt: &PromptText, buffers: &Preview<'a>) {
-    let block_config = |title| {
+fn layout_and_render<'a>(
+    frame: &mut Frame,
+    prompt: &PromptText,
+    error: &Option<(usize, String)>,
+    join: &Join,
+    note: Option<&str>,
+    buffers: &Preview<'a>,
+) {
+    let block_config = |title: Line<'a>| {
         Block::default()
             .title_position(ratatui::widgets::block::Position::Top)
             .title_alignment(ratatui::layout::Alignment::Left)
@@ -128,10 +225,30 @@ fn layout_and_render<'a>(frame: &mut Frame, prompt: &PromptText, buffers: &Previ
 
     let main_layout = Layout::default()
         .direction(Direction::Vertical)
-        .constraints(vec![Constraint::Length(3), Constraint::Fill(1)])
+        .constraints(vec![
+            Constraint::Length(if error.is_some() { 4 } else { 3 }),
+            Constraint::Fill(1),
+        ])
         .split(frame.size());
 
-    let prompt = Paragraph::new(prompt.dump()).block(block_config("Prompt"));
+    // a caret under the offending character with the reason next to it
+    let mut text = Text::raw(prompt.dump());
+    if let Some((offset, message)) = error {
+        text.lines.push(
+            Line::raw(format!("{:>width$} {message}", "^", width = offset + 1))
+                .style(Style::new().red()),
+        );
+    }
+    let mut title = Line::raw(format!(
+        "Prompt (join: {join}, Tab to change, Ctrl+Y to copy)"
+    ));
+    if let Some(note) = note {
+        title.spans.push(Span::styled(
+            format!(" {note} "),
+            Style::new().black().on_yellow(),
+        ));
+    }
+    let prompt = Paragraph::new(text).block(block_config(title));
 
     // let scroll = Scrollbar::new(ScrollbarOrientation::VerticalRight);
     // let mut scrollstate = ScrollbarState::new(buffers.len()).position(0);
@@ -13,16 +13,14 @@ mod widgets {
 
     use std::rc::Rc;
 
-    use config::Base;
+    use config::{Base, PIN_SYMBOL};
 
     use ratatui::{
         layout::{Constraint, Direction, Layout, Rect},
         prelude::Style,
         style::Stylize,
         text::Text,
-        widgets::{
-            Block, Borders, Paragraph, Scrollbar, ScrollbarOrientation, ScrollbarState, Widget,
-        },
+        widgets::{Block, Borders, Paragraph, Widget},
     };
 
     //TODO: add number parsing abilities in this
@@ -32,8 +30,10 @@ mod widgets {
     }
 
     pub(crate) enum ShuffleOperation {
-        Pop(u8),
-        Push(u8),
+        Pop(usize),
+        Push(usize),
+        PopPinned(usize),
+        PushPinned(usize),
     }
 
     impl PromptText {
@@ -47,78 +47,117 @@ mod widgets {
         pub fn push(&mut self, x: char) -> Option<ShuffleOperation> {
             // TODO: need a constraint on the length at some point
             let cursor = self.field.pop().unwrap();
+            let open = self.open_index();
+            let pinned = self.field.ends_with(PIN_SYMBOL);
             self.field.push(x);
+            let buffer = match open {
+                Some(start) if x == ']' => self.bracketed(start, self.field.len() - 1),
+                // digits of an index are only counted once it is closed
+                Some(_) => None,
+                None => x.to_digit(self.base as u32).map(|n| (pinned, n as usize)),
+            };
             self.field.push(cursor);
-            if let Some(n) = x.to_digit(self.base as u32) {
-                Some(ShuffleOperation::Push(n as u8))
-            } else {
-                None
-            }
+            buffer.map(|(pinned, n)| {
+                if pinned {
+                    ShuffleOperation::PushPinned(n)
+                } else {
+                    ShuffleOperation::Push(n)
+                }
+            })
         }
         pub fn pop(&mut self) -> Option<ShuffleOperation> {
             // 3 because the cursor character is 3 byte unicode
             if self.field.len() > 3 {
                 let cursor = self.field.pop().unwrap();
                 let x = self.field.pop().unwrap();
+                let open = self.open_index();
+                let buffer = match open {
+                    Some(start) if x == ']' => self.bracketed(start, self.field.len()),
+                    Some(_) => None,
+                    None => x
+                        .to_digit(self.base as u32)
+                        .map(|n| (self.field.ends_with(PIN_SYMBOL), n as usize)),
+                };
                 self.field.push(cursor);
-                if let Some(n) = x.to_digit(self.base as u32) {
-                    Some(ShuffleOperation::Pop(n as u8))
-                } else {
-                    None
-                }
+                buffer.map(|(pinned, n)| {
+                    if pinned {
+                        ShuffleOperation::PopPinned(n)
+                    } else {
+                        ShuffleOperation::Pop(n)
+                    }
+                })
             } else {
                 None
             }
         }
+        // where the [ of an index that is not closed yet sits
+        fn open_index(&self) -> Option<usize> {
+            let open = self.field.rfind('[')?;
+            match self.field.rfind(']') {
+                Some(close) if close > open => None,
+                _ => Some(open),
+            }
+        }
+        // the decimal index between the brackets at start and end, and
+        // whether it is preceded by the pin symbol
+        fn bracketed(&self, start: usize, end: usize) -> Option<(bool, usize)> {
+            let n = self.field[start + 1..end].parse().ok()?;
+            Some((self.field[..start].ends_with(PIN_SYMBOL), n))
+        }
         // Return the prompt text for the rendering
         pub fn dump(&self) -> &str {
             &self.field
         }
         // Return input without the cursor symbol
-        fn return_input(&self) -> &str {
+        pub fn return_input(&self) -> &str {
             &self.field[0..&self.field.len() - 3]
         }
     }
 
     #[derive(Debug)]
     pub(crate) struct ShuffleList {
-        list_selected: Vec<u8>,
-        list_unselected: Vec<u8>,
-        size: u8,
-        selected: u8,
+        list_selected: Vec<usize>,
+        list_unselected: Vec<usize>,
+        size: usize,
+        selected: usize,
     }
 
     impl ShuffleList {
-        pub fn new(base: u8) -> Self {
+        pub fn new(base: usize) -> Self {
             Self {
-                list_selected: Vec::with_capacity(base as usize),
+                list_selected: Vec::with_capacity(base),
                 list_unselected: Vec::from_iter(0..base),
-                size: base as u8,
+                size: base,
                 selected: 0,
             }
         }
 
-        fn status(&self) -> Vec<u8> {
+        fn status(&self) -> Vec<usize> {
             let mut first = self.list_selected.clone();
             let mut second = self.list_unselected.clone();
             first.append(&mut second);
             first
         }
 
-        fn select(&mut self, n: u8) {
+        fn select(&mut self, n: usize) {
             assert!(self.selected <= self.size);
-            let idx = Self::search(&self.list_unselected, n).unwrap();
-            let ele = self.list_unselected.remove(idx as usize);
+            // the same buffer can be typed twice, it is only selected once
+            let Some(idx) = Self::search(&self.list_unselected, n) else {
+                return;
+            };
+            let ele = self.list_unselected.remove(idx);
             assert!(ele == n);
             self.selected += 1;
             self.list_selected.push(n);
             assert!(self.selected <= self.size);
-            assert!(self.list_selected.len() + self.list_unselected.len() == self.size as usize);
+            assert!(self.list_selected.len() + self.list_unselected.len() == self.size);
         }
 
-        fn unselect(&mut self, n: u8) {
-            let idx = Self::search(&self.list_selected, n).unwrap();
-            let ele = self.list_selected.remove(idx as usize);
+        fn unselect(&mut self, n: usize) {
+            let Some(idx) = Self::search(&self.list_selected, n) else {
+                return;
+            };
+            let ele = self.list_selected.remove(idx);
             assert!(ele == n);
             self.selected -= 1;
             // find the appropriate location to insert n so that it turn out sorted
@@ -129,20 +168,32 @@ mod widgets {
                         idx += 1;
                     }
                 }
-                idx as usize
+                idx
             };
             self.list_unselected.insert(iidx, n);
-            assert!(self.list_selected.len() + self.list_unselected.len() == self.size as usize);
+            assert!(self.list_selected.len() + self.list_unselected.len() == self.size);
+        }
+
+        // makes room for count more blocks at position at, the ones from
+        // there on move up by as much
+        fn insert(&mut self, at: usize, count: usize) {
+            let moved = self.list_selected.iter_mut();
+            for i in moved.chain(self.list_unselected.iter_mut()) {
+                if *i >= at {
+                    *i += count;
+                }
+            }
+            self.list_unselected.extend(at..at + count);
+            self.list_unselected.sort_unstable();
+            self.size += count;
         }
 
         // there must be no duplicates
-        fn search(hay: &[u8], pin: u8) -> Option<u8> {
-            let mut c = 0;
-            for i in hay {
+        fn search(hay: &[usize], pin: usize) -> Option<usize> {
+            for (c, i) in hay.iter().enumerate() {
                 if *i == pin {
                     return Some(c);
                 }
-                c += 1
             }
             None
         }
@@ -151,13 +202,18 @@ mod widgets {
     // TODO: The layouting will be handled here too, so get to it
     // TODO: add scrollbar
     // And the blocks will be recreated if the size changed
+    #[allow(dead_code)]
     pub(crate) struct Preview<'a> {
         // TEXT
         // obtained from db, split by lines already
         raw_buffer: Vec<Vec<String>>,
         no_of_lines: Vec<usize>,
-        // number of blocks
-        no_of_blocks: u8,
+        // number of blocks, the pinned ones come after the stack
+        no_of_blocks: usize,
+        no_of_pinned: usize,
+        // how many of each were there up front, the ones loaded later for a
+        // bracketed index only show up while they are selected
+        listed: (usize, usize),
 
         // SHUFFLE LIST
         // order of text this will work with selected to determine how many to color
@@ -174,27 +230,20 @@ mod widgets {
     }
 
     impl<'a> Preview<'a> {
-        pub fn new(blobs: Vec<String>) -> Self {
+        pub fn new(blobs: Vec<String>, pinned: Vec<String>) -> Self {
             let mut preview = {
-                let no_of_blocks = blobs.len() as u8;
+                let no_of_pinned = pinned.len();
+                let no_of_blocks = blobs.len() + pinned.len();
+                let listed = (blobs.len(), pinned.len());
                 let order_of_blocks = ShuffleList::new(no_of_blocks);
                 // let scroll = Scrollbar::new(ScrollbarOrientation::VerticalRight);
                 // TODO: Should wrap this in a smart pointer
                 // let scrollstate = ScrollbarState::new(no_of_blocks as usize).position(0);
 
-                let (raw_buffer, no_of_lines): (Vec<Vec<String>>, Vec<usize>) = blobs
-                    .into_iter()
-                    .map(|b| {
-                        let lines = b
-                            .split('\n')
-                            .map(|x| x.to_string())
-                            .collect::<Vec<String>>();
-                        let len = lines.len();
-                        (lines, len)
-                    })
-                    .collect();
+                let (raw_buffer, no_of_lines): (Vec<Vec<String>>, Vec<usize>) =
+                    blobs.into_iter().chain(pinned).map(Self::lines).unzip();
                 assert_eq!(raw_buffer.len(), no_of_lines.len());
-                assert_eq!(raw_buffer.len(), no_of_blocks as usize);
+                assert_eq!(raw_buffer.len(), no_of_blocks);
 
                 Self {
                     raw_buffer,
@@ -202,6 +251,8 @@ mod widgets {
                     blocks: Vec::new(),
                     order_of_blocks,
                     no_of_blocks,
+                    no_of_pinned,
+                    listed,
                     size: None,
                     // scroll_bar: (scroll, scrollstate),
                 }
@@ -210,30 +261,78 @@ mod widgets {
             preview
         }
 
+        fn lines(blob: String) -> (Vec<String>, usize) {
+            let lines = blob
+                .split('\n')
+                .map(|x| x.to_string())
+                .collect::<Vec<String>>();
+            let len = lines.len();
+            (lines, len)
+        }
+
         // this function will modify the blocks as neeeded
         // TODO: make this function actually useful with dynamic values
         pub fn make_blocks(&mut self) {
-            let mut blocks = Vec::with_capacity(self.no_of_blocks as usize);
-            for i in 0..(self.no_of_blocks as usize) {
-                blocks.push(
-                    Paragraph::new(Text::raw({
-                        let lines: Vec<String> = self.raw_buffer[i].clone();
-                        lines
-                            .into_iter()
-                            .take(5)
-                            .fold(String::new(), |mut lines, line| {
-                                lines.push_str(&line);
-                                lines.push('\n');
-                                lines
-                            })
-                    }))
-                    .block(Block::new().borders(Borders::all()).title(format!("{}", i))),
-                );
+            self.blocks = (0..self.no_of_blocks).map(|i| self.make_block(i)).collect();
+        }
+
+        // the nth block, with a green border while it is selected
+        fn make_block(&self, n: usize) -> Paragraph<'a> {
+            let lines: Vec<String> = self.raw_buffer[n].clone();
+            let text = lines
+                .into_iter()
+                .take(5)
+                .fold(String::new(), |mut lines, line| {
+                    lines.push_str(&line);
+                    lines.push('\n');
+                    lines
+                });
+            let mut block = Block::new().borders(Borders::all()).title(self.label(n));
+            if self.order_of_blocks.list_selected.contains(&n) {
+                block = block.border_style(Style::new().green());
+            }
+            Paragraph::new(Text::raw(text)).block(block)
+        }
+
+        // how many entries of the stack or of the pinned list have a block
+        pub fn loaded(&self, pinned: bool) -> usize {
+            match pinned {
+                true => self.no_of_pinned,
+                false => self.stack_len(),
+            }
+        }
+
+        // adds the entries that come after the loaded ones, so a bracketed
+        // index past them can be selected
+        pub fn load(&mut self, pinned: bool, blobs: Vec<String>) {
+            let at = match pinned {
+                true => self.no_of_blocks,
+                false => self.stack_len(),
+            };
+            let count = blobs.len();
+            let (raw_buffer, no_of_lines): (Vec<_>, Vec<_>) =
+                blobs.into_iter().map(Self::lines).unzip();
+            self.raw_buffer.splice(at..at, raw_buffer);
+            self.no_of_lines.splice(at..at, no_of_lines);
+            self.no_of_blocks += count;
+            if pinned {
+                self.no_of_pinned += count;
+            }
+            self.order_of_blocks.insert(at, count);
+            // the pinned labels count from the end of the stack
+            self.make_blocks();
+        }
+
+        // blocks that were not there up front are left out unless selected
+        fn listed(&self, n: usize) -> bool {
+            match n.checked_sub(self.stack_len()) {
+                None => n < self.listed.0,
+                Some(n) => n < self.listed.1,
             }
-            self.blocks = blocks;
         }
 
         // TODO: call this in render loop
+        #[allow(dead_code)]
         fn size_changed(&mut self, area: Rect) {
             if let Some(size) = self.size {
                 if size != area {
@@ -246,41 +345,84 @@ mod widgets {
             }
         }
 
-        pub fn select(&mut self, n: u8) {
-            if n >= self.no_of_blocks {
+        fn stack_len(&self) -> usize {
+            self.no_of_blocks - self.no_of_pinned
+        }
+
+        // title of the nth block, pinned ones carry the pin symbol
+        fn label(&self, n: usize) -> String {
+            if n < self.stack_len() {
+                format!("{}", n)
+            } else {
+                format!("{}{}", PIN_SYMBOL, n - self.stack_len())
+            }
+        }
+
+        pub fn select(&mut self, n: usize) {
+            if n >= self.stack_len() {
+                return;
+            }
+            self.select_block(n)
+        }
+
+        pub fn select_pinned(&mut self, n: usize) {
+            if n >= self.no_of_pinned {
+                return;
+            }
+            self.select_block(self.stack_len() + n)
+        }
+
+        pub fn unselect(&mut self, n: usize) {
+            if n >= self.stack_len() {
                 return;
             }
-            let block: Paragraph<'_> = self.blocks[n as usize].clone();
-            let new_block = block.block(
-                Block::default()
-                    .borders(Borders::all())
-                    .title(format!("{}", n))
-                    .border_style(Style::new().green()),
-            );
-            self.blocks[n as usize] = new_block;
-            self.order_of_blocks.select(n)
+            self.unselect_block(n)
         }
 
-        pub fn unselect(&mut self, n: u8) {
-            if n >= self.no_of_blocks {
+        pub fn unselect_pinned(&mut self, n: usize) {
+            if n >= self.no_of_pinned {
                 return;
             }
-            let block: Paragraph<'_> = self.blocks[n as usize].clone();
-            let new_block = block.block(
-                Block::default()
-                    .borders(Borders::all())
-                    .title(format!("{}", n)),
-            );
-            self.blocks[n as usize] = new_block;
-            self.order_of_blocks.unselect(n)
+            self.unselect_block(self.stack_len() + n)
+        }
+
+        fn select_block(&mut self, n: usize) {
+            self.order_of_blocks.select(n);
+            self.blocks[n] = self.make_block(n);
+        }
+
+        fn unselect_block(&mut self, n: usize) {
+            self.order_of_blocks.unselect(n);
+            self.blocks[n] = self.make_block(n);
         }
 
-        pub fn yeild_list(self) -> Vec<Vec<String>> {
-            let mut out = Vec::with_capacity(self.order_of_blocks.selected as usize);
-            for i in self.order_of_blocks.list_selected {
-                out.push(self.raw_buffer[i as usize].clone())
+        // one bordered box holding the given blocks stacked on top of each other
+        fn render_section(
+            &self,
+            order: &[usize],
+            title: &str,
+            area: Rect,
+            buf: &mut ratatui::prelude::Buffer,
+        ) {
+            let outer_block = Block::new()
+                .borders(Borders::all())
+                .title(title.to_string());
+            let inner_area = outer_block.inner(area);
+            if !order.is_empty() {
+                let n = order.len() as u16;
+                let max = inner_area.height.saturating_sub(2 * n) / n;
+                let layout: Rc<[Rect]> = {
+                    Layout::default()
+                        .direction(Direction::Vertical)
+                        .constraints(vec![Constraint::Max(max); order.len()])
+                        .split(inner_area)
+                };
+                for (idx, i) in order.iter().enumerate() {
+                    let block = self.blocks[*i].clone();
+                    block.render(layout[idx], buf);
+                }
             }
-            out
+            outer_block.render(area, buf);
         }
     }
 
@@ -288,28 +430,63 @@ mod widgets {
     impl<'a> Widget for &Preview<'a> {
         fn render(self, area: Rect, buf: &mut ratatui::prelude::Buffer) {
             // HOWTO: render scrollable list
-            let outer_block = Block::new().borders(Borders::all()).title("Preview");
-            let inner_area = outer_block.inner(area);
-            let max =
-                (inner_area.height - (2 * self.no_of_blocks as u16)) / self.no_of_blocks as u16;
-            let layout: Rc<[Rect]> = {
-                Layout::default()
-                    .direction(Direction::Vertical)
-                    .constraints(vec![Constraint::Max(max); self.no_of_blocks as usize])
-                    .split(inner_area)
-            };
-
-            let state = self.order_of_blocks.status();
+            let selected = &self.order_of_blocks.list_selected;
+            let state = self.order_of_blocks.status().into_iter();
+            let state = state.filter(|i| selected.contains(i) || self.listed(*i));
             // eprintln!("{:?}", &state);
             // TODO: will need to reorder this later
-            let mut idx = 0;
-            for i in state {
-                let block = self.blocks[i as usize].clone();
-                block.render(layout[idx], buf);
-                idx += 1;
+            let stack_len = self.stack_len();
+            let (stack, pinned): (Vec<usize>, Vec<usize>) = state.partition(|i| *i < stack_len);
+            if pinned.is_empty() {
+                self.render_section(&stack, "Preview", area, buf);
+            } else {
+                let total = (stack.len() + pinned.len()) as u32;
+                let layout = Layout::default()
+                    .direction(Direction::Vertical)
+                    .constraints([
+                        Constraint::Ratio(stack.len() as u32, total),
+                        Constraint::Ratio(pinned.len() as u32, total),
+                    ])
+                    .split(area);
+                self.render_section(&stack, "Preview", layout[0], buf);
+                self.render_section(&pinned, "Pinned", layout[1], buf);
             }
-            assert!(idx == self.no_of_blocks as usize);
-            outer_block.render(area, buf);
         }
     }
+
+    #[test]
+    fn deep_selection() {
+        let mut prompt = PromptText::new(Base::Octal);
+        let picked: Vec<_> = "1 [300] @0"
+            .chars()
+            .filter_map(|x| prompt.push(x))
+            .collect();
+        assert!(matches!(
+            picked[..],
+            [
+                ShuffleOperation::Push(1),
+                ShuffleOperation::Push(300),
+                ShuffleOperation::PushPinned(0)
+            ]
+        ));
+
+        let stack = |range: std::ops::Range<usize>| range.map(|x| x.to_string()).collect();
+        let mut preview = Preview::new(stack(0..8), vec!["pinned".to_string()]);
+        preview.select(1);
+        preview.select_pinned(0);
+        // nothing happens until the entries down to it are loaded
+        preview.select(300);
+        assert_eq!(preview.order_of_blocks.list_selected, [1, 8]);
+        preview.load(false, stack(8..301));
+        assert_eq!(preview.loaded(false), 301);
+        preview.select(300);
+        // the pinned block moved up past the loaded ones
+        assert_eq!(preview.order_of_blocks.list_selected, [1, 301, 300]);
+        assert_eq!(preview.label(301), format!("{PIN_SYMBOL}0"));
+        preview.unselect_pinned(0);
+        assert_eq!(preview.order_of_blocks.list_selected, [1, 300]);
+        // the rest only gets drawn while it is selected
+        let shown = (0..preview.no_of_blocks).filter(|&i| preview.listed(i));
+        assert_eq!(shown.count(), 9);
+    }
 }